@@ -35,5 +35,6 @@ async fn http_main() -> io::Result<()> {
     let mut server = HttpServer::new();
     server.service(EchoService);
 
-    dhttp::serve_tcp("[::]:8080", server).await
+    dhttp::serve_tcp("[::]:8080", server).await?;
+    Ok(())
 }
@@ -32,5 +32,6 @@ async fn http_main() -> io::Result<()> {
     let mut server = HttpServer::new();
     server.service(ErrorService);
 
-    dhttp::serve_tcp("[::]:8080", server).await
+    dhttp::serve_tcp("[::]:8080", server).await?;
+    Ok(())
 }
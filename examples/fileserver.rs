@@ -52,7 +52,8 @@ async fn list_dir(route: &str, path: &Path) -> io::Result<String> {
 async fn http_main() -> io::Result<()> {
     let mut server = HttpServer::new();
     server.service(FileServer);
-    dhttp::serve_tcp("[::]:8080", server).await
+    dhttp::serve_tcp("[::]:8080", server).await?;
+    Ok(())
 }
 
 fn main() -> io::Result<()> {
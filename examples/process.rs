@@ -0,0 +1,79 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::process::{ExitStatus, Stdio};
+use std::task::{Context, Poll, ready};
+
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::process::{ChildStdout, Command};
+
+use dhttp::prelude::*;
+use dhttp::reqres::res;
+
+struct ArchiveService;
+
+impl HttpService for ArchiveService {
+    async fn request(&self, _route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+        // Archive of the working directory, made while it is being downloaded
+        let mut command = Command::new("tar");
+        command.args(["-c", "."]);
+        // `NotFound` would become 404 otherwise
+        let stdout = ProcessStdout::spawn(command).map_err(io::Error::other)?;
+        let mut res = res::stream("application/x-tar", stdout);
+        res.add_header("Content-Disposition", "attachment; filename=\"archive.tar\"");
+        Ok(res)
+    }
+}
+
+/// Stdout of a child process as a response body
+///
+/// If the process fails, the response is cut off with an error instead of ending normally, so
+/// the client can tell that it is incomplete. If the client disconnects, the body is dropped,
+/// and `kill_on_drop` kills the process
+struct ProcessStdout {
+    stdout: ChildStdout,
+    exit: Option<Pin<Box<dyn Future<Output = io::Result<ExitStatus>> + Send>>>,
+}
+
+impl ProcessStdout {
+    fn spawn(mut command: Command) -> io::Result<ProcessStdout> {
+        command.stdin(Stdio::null()).stdout(Stdio::piped()).kill_on_drop(true);
+        let mut child = command.spawn()?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        // Owns the child, so it is killed when this is dropped
+        let exit = Box::pin(async move { child.wait().await });
+        Ok(ProcessStdout { stdout, exit: Some(exit) })
+    }
+}
+
+impl AsyncRead for ProcessStdout {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut self.stdout).poll_read(cx, buf))?;
+        if buf.filled().len() > before || buf.remaining() == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        // Stdout is closed, wait for the exit status before ending the body
+        if let Some(exit) = &mut self.exit {
+            let status = ready!(exit.as_mut().poll(cx))?;
+            self.exit = None;
+            if !status.success() {
+                return Poll::Ready(Err(io::Error::other(format!("process failed: {status}"))));
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn main() -> io::Result<()> {
+    dhttp::tokio_rt()?.block_on(http_main())
+}
+
+async fn http_main() -> io::Result<()> {
+    let mut server = HttpServer::new();
+    server.service(ArchiveService);
+
+    dhttp::serve_tcp("[::]:8080", server).await?;
+    Ok(())
+}
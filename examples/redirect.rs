@@ -19,5 +19,6 @@ async fn http_main() -> io::Result<()> {
     let mut server = HttpServer::new();
     server.service(Redirect("https://google.com"));
 
-    dhttp::serve_tcp("[::]:8080", server).await
+    dhttp::serve_tcp("[::]:8080", server).await?;
+    Ok(())
 }
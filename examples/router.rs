@@ -43,5 +43,6 @@ async fn http_main() -> io::Result<()> {
     server.service(router);
     server.error_handler(JsonErrorHandler);
 
-    dhttp::serve_tcp("[::]:8080", server).await
+    dhttp::serve_tcp("[::]:8080", server).await?;
+    Ok(())
 }
@@ -29,5 +29,6 @@ async fn http_main() -> io::Result<()> {
     let name = "DrakoHTTP".to_string();
     server.service(MyService { name });
 
-    dhttp::serve_tcp("[::]:8080", server).await
+    dhttp::serve_tcp("[::]:8080", server).await?;
+    Ok(())
 }
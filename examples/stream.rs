@@ -0,0 +1,44 @@
+use std::io;
+
+use tokio::io::{AsyncWriteExt, DuplexStream};
+
+use dhttp::prelude::*;
+use dhttp::reqres::res;
+
+struct ExportService;
+
+impl HttpService for ExportService {
+    async fn request(&self, _route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+        // Everything written to `tx` is streamed to the client,
+        // and the writer waits while the buffer is full
+        let (mut tx, rx) = tokio::io::duplex(16384);
+        tokio::spawn(async move {
+            // Error means that client has disconnected
+            let _ = write_json(&mut tx).await;
+        });
+        Ok(res::stream("application/json", rx))
+    }
+}
+
+// Writes a huge JSON array without building it in memory
+async fn write_json(tx: &mut DuplexStream) -> io::Result<()> {
+    tx.write_all(b"[").await?;
+    for i in 0..1_000_000 {
+        if i != 0 { tx.write_all(b",").await?; }
+        let item = format!(r#"{{"id": {i}, "name": "item {i}"}}"#);
+        tx.write_all(item.as_bytes()).await?;
+    }
+    tx.write_all(b"]\n").await
+}
+
+fn main() -> io::Result<()> {
+    dhttp::tokio_rt()?.block_on(http_main())
+}
+
+async fn http_main() -> io::Result<()> {
+    let mut server = HttpServer::new();
+    server.service(ExportService);
+
+    dhttp::serve_tcp("[::]:8080", server).await?;
+    Ok(())
+}
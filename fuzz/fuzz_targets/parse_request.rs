@@ -0,0 +1,17 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let strict = dhttp::fuzz::parse_request(data, true);
+    let lenient = dhttp::fuzz::parse_request(data, false);
+    // Strict mode only rejects more, it never parses differently
+    if let Some(strict) = strict {
+        let lenient = lenient.expect("accepted by strict parser only");
+        assert_eq!(strict.method, lenient.method);
+        assert_eq!(strict.route, lenient.route);
+        assert_eq!(strict.headers.len(), lenient.headers.len());
+        assert_eq!(strict.len, lenient.len);
+        assert_eq!(strict.chunked, lenient.chunked);
+    }
+});
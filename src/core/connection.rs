@@ -2,9 +2,16 @@
 
 use std::io::{self, ErrorKind};
 use std::pin::Pin;
-use std::task::{Context, Poll, ready};
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll, Waker, ready};
+use std::time::Duration;
 
-use tokio::io::{AsyncRead, AsyncBufRead, AsyncWrite, ReadBuf, Take};
+use tokio::io::{AsyncRead, AsyncBufRead, AsyncWrite, AsyncReadExt, AsyncBufReadExt, ReadBuf, Take};
+use tokio::time::{Instant, Sleep, sleep_until};
+use blake3_lite::Hasher;
+
+use crate::reqres::{HttpHeader, HttpRequest, StatusCode};
+use crate::core::{HttpProgress, HttpResult};
 
 /// Async buffered reader stream
 pub trait HttpRead: AsyncBufRead + Unpin + Send + Sync {}
@@ -18,19 +25,177 @@ impl<T: AsyncWrite + Unpin + Send + Sync> HttpWrite for T {}
 pub trait HttpConnection: HttpRead + HttpWrite {}
 impl<T: HttpRead + HttpWrite> HttpConnection for T {}
 
+/// Accepts the request body, sending `100 Continue` if client is waiting for it
+///
+/// Only needed when [`HttpServer::implicit_continue`] is disabled,
+/// otherwise the first read from the body does the same
+///
+/// [`HttpServer::implicit_continue`]: crate::server::HttpServer::implicit_continue
+pub async fn accept_body(body: &mut dyn HttpRead) -> io::Result<()> {
+    // Empty read is the signal
+    body.read(&mut []).await?;
+    Ok(())
+}
+
+/// Reads the whole body, up to `limit` bytes
+///
+/// # Errors
+/// - [`StatusCode::REQUEST_ENTITY_TOO_LARGE`] if body is longer than `limit`
+pub async fn read_to_bytes(body: &mut dyn HttpRead, limit: u64) -> HttpResult<Vec<u8>> {
+    let mut data = vec![];
+    body.take(limit.saturating_add(1)).read_to_end(&mut data).await?;
+    if data.len() as u64 > limit {
+        return Err(StatusCode::REQUEST_ENTITY_TOO_LARGE.into());
+    }
+    Ok(data)
+}
+
+/// Reads the whole body as UTF-8 text, up to `limit` bytes
+///
+/// # Errors
+/// - [`StatusCode::REQUEST_ENTITY_TOO_LARGE`] if body is longer than `limit`
+/// - [`StatusCode::BAD_REQUEST`] if body is not valid UTF-8
+pub async fn read_to_string(body: &mut dyn HttpRead, limit: u64) -> HttpResult<String> {
+    let data = read_to_bytes(body, limit).await?;
+    String::from_utf8(data).map_err(|_| StatusCode::BAD_REQUEST.into())
+}
+
+/// Reads the body line by line, for formats like NDJSON
+///
+/// Lines are limited to `max_len` bytes, so memory use stays bounded
+pub fn read_lines(body: &mut dyn HttpRead, max_len: usize) -> BodyLines<'_> {
+    BodyLines { body, max_len }
+}
+
+/// Lines of the body, created by [`read_lines`]
+pub struct BodyLines<'a> {
+    body: &'a mut dyn HttpRead,
+    max_len: usize,
+}
+
+impl BodyLines<'_> {
+    /// Reads the next line without `\n` or `\r\n`, or `None` at the end of body
+    ///
+    /// # Errors
+    /// - [`StatusCode::REQUEST_ENTITY_TOO_LARGE`] if line is longer than `max_len`
+    /// - [`StatusCode::BAD_REQUEST`] if line is not valid UTF-8
+    pub async fn next_line(&mut self) -> HttpResult<Option<String>> {
+        let mut line = vec![];
+        loop {
+            let buf = self.body.fill_buf().await?;
+            if buf.is_empty() {
+                // Last line may not have a newline
+                if line.is_empty() { return Ok(None); }
+                break;
+            }
+
+            let (chunk, done) = match buf.iter().position(|&c| c == b'\n') {
+                Some(end) => (&buf[..=end], true),
+                None => (buf, false),
+            };
+            // +2 for \r\n
+            if line.len() + chunk.len() > self.max_len + 2 {
+                return Err(StatusCode::REQUEST_ENTITY_TOO_LARGE.into());
+            }
+            line.extend_from_slice(chunk);
+            let used = chunk.len();
+            self.body.consume(used);
+            if done { break; }
+        }
+
+        if line.ends_with(b"\n") { line.pop(); }
+        if line.ends_with(b"\r") { line.pop(); }
+        if line.len() > self.max_len {
+            return Err(StatusCode::REQUEST_ENTITY_TOO_LARGE.into());
+        }
+        let line = String::from_utf8(line).map_err(|_| StatusCode::BAD_REQUEST)?;
+        Ok(Some(line))
+    }
+}
+
+/// Hashes the body with Blake3 while it is being read, so it can be verified without a second pass
+///
+/// It is an [`HttpRead`] itself, so it can be used in place of the body:
+/// ```
+/// # use dhttp::prelude::*;
+/// # use dhttp::reqres::res;
+/// use tokio::io::AsyncReadExt;
+/// use dhttp::core::connection::HashRead;
+///
+/// async fn upload(_route: &str, _req: &HttpRequest, body: &mut dyn HttpRead) -> HttpResult {
+///     let mut body = HashRead::new(body);
+///     let mut data = vec![];
+///     body.read_to_end(&mut data).await?;
+///     let hash = body.finalize();
+///     Ok(res::text(format!("{} bytes, hash starts with {:02x}\n", data.len(), hash[0])))
+/// }
+/// ```
+pub struct HashRead<R> {
+    inner: R,
+    hasher: Hasher,
+}
+
+impl<R: HttpRead> HashRead<R> {
+    pub fn new(inner: R) -> HashRead<R> {
+        HashRead { inner, hasher: Hasher::new() }
+    }
+
+    /// Hash of everything that was read
+    pub fn finalize(self) -> [u8; 32] {
+        let mut hash = [0; 32];
+        self.hasher.finalize(&mut hash);
+        hash
+    }
+}
+
+impl<R: HttpRead> AsyncRead for HashRead<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        self.hasher.update(&buf.filled()[before..]);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R: HttpRead> AsyncBufRead for HashRead<R> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut Pin::into_inner(self).inner).poll_fill_buf(cx)
+    }
+
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = Pin::into_inner(self);
+        // Data was already filled by the caller, so this is ready without waiting
+        let mut cx = Context::from_waker(Waker::noop());
+        if let Poll::Ready(Ok(buf)) = Pin::new(&mut this.inner).poll_fill_buf(&mut cx) {
+            this.hasher.update(&buf[..amt.min(buf.len())]);
+        }
+        Pin::new(&mut this.inner).consume(amt)
+    }
+}
+
 pub(crate) struct EmitContinue<T: HttpConnection> {
     pub conn: Take<T>,
     pub to_send: &'static [u8],
+    /// Send on any read, not only on [`accept_body`]
+    pub implicit: bool,
 }
 
-impl<T: HttpConnection> AsyncRead for EmitContinue<T> {
-    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+impl<T: HttpConnection> EmitContinue<T> {
+    fn poll_continue(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
         while !self.to_send.is_empty() {
-            let to_send = self.to_send;
-            let written = ready!(Pin::new(self.conn.get_mut()).poll_write(cx, to_send))?;
+            let written = ready!(Pin::new(self.conn.get_mut()).poll_write(cx, self.to_send))?;
             if written == 0 { return Poll::Ready(Err(ErrorKind::WriteZero.into())); }
             self.to_send = &self.to_send[written..];
         }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: HttpConnection> AsyncRead for EmitContinue<T> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        if self.implicit || buf.remaining() == 0 {
+            ready!(self.poll_continue(cx))?;
+        }
 
         Pin::new(&mut self.conn).poll_read(cx, buf)
     }
@@ -38,10 +203,291 @@ impl<T: HttpConnection> AsyncRead for EmitContinue<T> {
 
 impl<T: HttpConnection> AsyncBufRead for EmitContinue<T> {
     fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
-        Pin::new(&mut Pin::into_inner(self).conn).poll_fill_buf(cx)
+        let this = Pin::into_inner(self);
+        if this.implicit {
+            ready!(this.poll_continue(cx))?;
+        }
+        Pin::new(&mut this.conn).poll_fill_buf(cx)
     }
 
     fn consume(self: Pin<&mut Self>, amt: usize) {
         Pin::new(&mut Pin::into_inner(self).conn).consume(amt)
     }
 }
+
+// Longest chunk size line or trailer line
+const MAX_CHUNK_LINE: usize = 4096;
+// Longest trailer section, trailers are dropped unless captured
+const MAX_TRAILERS: usize = 65536;
+
+#[derive(Clone, Copy)]
+enum Chunk {
+    /// Reading the `1a;ext=value` line
+    Size,
+    /// Bytes left in the current chunk
+    Data(u64),
+    /// Reading CRLF after the chunk data
+    DataEnd,
+    /// Reading trailer lines after the last chunk, with their total length
+    Trailers(usize),
+    Done,
+}
+
+/// Decodes a request body sent with `Transfer-Encoding: chunked`
+///
+/// Malformed chunks fail reads with [`ErrorKind::InvalidData`], which is `400 Bad request`.
+/// Chunks over `max_chunk`, or more than `max_total` bytes in all, fail with [`ErrorKind::FileTooLarge`],
+/// which is `413 Request entity too large`. Both are checked on the size line, before the data is read
+pub(crate) struct ChunkedBody<T> {
+    inner: T,
+    state: Chunk,
+    line: Vec<u8>,
+    max_chunk: u64,
+    max_total: u64,
+    /// Sum of the chunk sizes so far
+    total: u64,
+    /// Where trailers go when the body ends, with the ones read so far
+    trailers: Option<(Arc<OnceLock<Vec<HttpHeader>>>, Vec<HttpHeader>)>,
+}
+
+impl<T: AsyncBufRead + Unpin> ChunkedBody<T> {
+    pub fn new(inner: T, max_chunk: u64, max_total: u64) -> ChunkedBody<T> {
+        ChunkedBody { inner, state: Chunk::Size, line: vec![], max_chunk, max_total, total: 0, trailers: None }
+    }
+
+    /// Keeps the trailers, they are put into `slot` after the last one was read
+    pub fn capture_trailers(&mut self, slot: Arc<OnceLock<Vec<HttpHeader>>>) {
+        self.trailers = Some((slot, vec![]));
+    }
+
+    /// Whether the whole body was read, including the last chunk and trailers
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, Chunk::Done)
+    }
+
+    /// Reads a line into `line`, without its ending
+    fn poll_line(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            let buf = ready!(Pin::new(&mut self.inner).poll_fill_buf(cx))?;
+            if buf.is_empty() { return Poll::Ready(Err(ErrorKind::UnexpectedEof.into())); }
+            let (chunk, done) = match buf.iter().position(|&c| c == b'\n') {
+                Some(end) => (&buf[..end], true),
+                None => (buf, false),
+            };
+            if self.line.len() + chunk.len() > MAX_CHUNK_LINE { return Poll::Ready(Err(malformed())); }
+            self.line.extend_from_slice(chunk);
+            let used = chunk.len() + done as usize;
+            Pin::new(&mut self.inner).consume(used);
+            if done {
+                if self.line.last() == Some(&b'\r') { self.line.pop(); }
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, "malformed chunked body")
+}
+
+/// Parses `1a` or `1a;ext=value` into the chunk size
+fn chunk_size(line: &[u8]) -> Option<u64> {
+    let line = std::str::from_utf8(line).ok()?;
+    let size = line.split_once(';').map_or(line, |(size, _ext)| size).trim_end_matches([' ', '\t']);
+    // from_str_radix would also accept `+`
+    if size.is_empty() || size.len() > 16 || !size.bytes().all(|b| b.is_ascii_hexdigit()) { return None; }
+    u64::from_str_radix(size, 16).ok()
+}
+
+impl<T: AsyncBufRead + Unpin> AsyncRead for ChunkedBody<T> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        // Empty read is the `accept_body` signal, it would wait for a chunk that is only sent after `100 Continue`
+        if buf.remaining() == 0 { return Pin::new(&mut self.inner).poll_read(cx, buf); }
+        let data = ready!(self.as_mut().poll_fill_buf(cx))?;
+        let len = data.len().min(buf.remaining());
+        buf.put_slice(&data[..len]);
+        self.consume(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncBufRead + Unpin> AsyncBufRead for ChunkedBody<T> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = Pin::into_inner(self);
+        loop {
+            match this.state {
+                Chunk::Data(0) => this.state = Chunk::DataEnd,
+                Chunk::Data(left) => {
+                    let buf = ready!(Pin::new(&mut this.inner).poll_fill_buf(cx))?;
+                    if buf.is_empty() { return Poll::Ready(Err(ErrorKind::UnexpectedEof.into())); }
+                    let len = buf.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+                    return Poll::Ready(Ok(&buf[..len]));
+                }
+                Chunk::Done => return Poll::Ready(Ok(&[])),
+                Chunk::Size => {
+                    ready!(this.poll_line(cx))?;
+                    let size = chunk_size(&this.line).ok_or_else(malformed)?;
+                    this.total = this.total.saturating_add(size);
+                    if size > this.max_chunk || this.total > this.max_total {
+                        return Poll::Ready(Err(io::Error::new(ErrorKind::FileTooLarge, "chunked body too large")));
+                    }
+                    this.state = if size == 0 { Chunk::Trailers(0) } else { Chunk::Data(size) };
+                }
+                Chunk::DataEnd => {
+                    ready!(this.poll_line(cx))?;
+                    if !this.line.is_empty() { return Poll::Ready(Err(malformed())); }
+                    this.state = Chunk::Size;
+                }
+                Chunk::Trailers(total) => {
+                    ready!(this.poll_line(cx))?;
+                    let total = total + this.line.len() + 2;
+                    if total > MAX_TRAILERS { return Poll::Ready(Err(malformed())); }
+                    if let Some((slot, trailers)) = &mut this.trailers {
+                        if this.line.is_empty() {
+                            let _ = slot.set(std::mem::take(trailers));
+                        } else {
+                            let line = std::str::from_utf8(&this.line).map_err(|_| malformed())?;
+                            let (name, value) = line.split_once(':').ok_or_else(malformed)?;
+                            trailers.push(HttpHeader { name: name.trim().to_string(), value: value.trim().to_string() });
+                        }
+                    }
+                    this.state = if this.line.is_empty() { Chunk::Done } else { Chunk::Trailers(total) };
+                }
+            }
+            this.line.clear();
+        }
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        if let Chunk::Data(left) = &mut self.state {
+            *left -= amt as u64;
+        }
+        Pin::new(&mut self.inner).consume(amt)
+    }
+}
+
+/// Reports transferred bytes to [`HttpProgress`]
+///
+/// Reads are reported as received, writes as sent
+pub(crate) struct Progress<'a, T> {
+    pub inner: T,
+    pub req: &'a HttpRequest,
+    pub progress: &'a dyn HttpProgress,
+    pub bytes: u64,
+}
+
+impl<'a, T> Progress<'a, T> {
+    pub fn new(inner: T, req: &'a HttpRequest, progress: &'a dyn HttpProgress) -> Progress<'a, T> {
+        Progress { inner, req, progress, bytes: 0 }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Progress<'_, T> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        self.bytes += (buf.filled().len() - before) as u64;
+        Poll::Ready(self.progress.received(self.req, self.bytes))
+    }
+}
+
+impl<T: AsyncBufRead + Unpin> AsyncBufRead for Progress<'_, T> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = Pin::into_inner(self);
+        // Consumed bytes are reported here, since consume() can't fail
+        this.progress.received(this.req, this.bytes)?;
+        Pin::new(&mut this.inner).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        self.bytes += amt as u64;
+        Pin::new(&mut self.inner).consume(amt)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Progress<'_, T> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let written = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.bytes += written as u64;
+        self.progress.sent(self.req, self.bytes)?;
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// Time that any transfer has before the rate is enforced
+const MIN_RATE_GRACE: Duration = Duration::from_secs(10);
+
+/// Fails writes when transfer rate falls below the minimum (in bytes per second)
+///
+/// Transfers that are done in [`MIN_RATE_GRACE`] are never affected
+pub(crate) struct MinRate<T> {
+    pub inner: T,
+    rate: u64,
+    start: Instant,
+    bytes: u64,
+    timer: Pin<Box<Sleep>>,
+}
+
+impl<T> MinRate<T> {
+    pub fn new(inner: T, rate: u64) -> MinRate<T> {
+        let start = Instant::now();
+        let timer = Box::pin(sleep_until(start + MIN_RATE_GRACE));
+        MinRate { inner, rate: rate.max(1), start, bytes: 0, timer }
+    }
+
+    /// Time by which more bytes have to be written
+    fn deadline(&self) -> Instant {
+        self.start + MIN_RATE_GRACE + Duration::from_millis(self.bytes.saturating_mul(1000) / self.rate)
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for MinRate<T> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncBufRead + Unpin> AsyncBufRead for MinRate<T> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        Pin::new(&mut Pin::into_inner(self).inner).poll_fill_buf(cx)
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        Pin::new(&mut self.inner).consume(amt)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for MinRate<T> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match Pin::new(&mut self.inner).poll_write(cx, buf) {
+            Poll::Ready(Ok(written)) => {
+                self.bytes += written as u64;
+                let deadline = self.deadline();
+                self.timer.as_mut().reset(deadline);
+                Poll::Ready(Ok(written))
+            }
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => {
+                // Client is not reading, check if it is too late already
+                ready!(self.timer.as_mut().poll(cx));
+                Poll::Ready(Err(io::Error::new(ErrorKind::TimedOut, "transfer rate is too low")))
+            }
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -2,7 +2,7 @@ use std::convert::Infallible;
 use std::io::{self, ErrorKind};
 use std::error::Error;
 
-use crate::reqres::StatusCode;
+use crate::reqres::{HttpHeader, StatusCode};
 
 /// How should this error be handled
 ///
@@ -39,6 +39,11 @@ pub trait HttpError: Error + Send + 'static {
     fn status_code(&self) -> StatusCode {
         StatusCode::INTERNAL_SERVER_ERROR
     }
+
+    /// Headers added to the error response, like `Allow` of `405 Method not allowed`
+    fn headers(&self) -> Vec<HttpHeader> {
+        vec![]
+    }
 }
 
 impl<E: HttpError> From<E> for Box<dyn HttpError> {
@@ -86,6 +91,10 @@ impl HttpError for io::Error {
         match self.kind() {
             ErrorKind::NotFound | ErrorKind::NotADirectory => StatusCode::NOT_FOUND,
             ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+            // Malformed request body, like a broken chunk
+            ErrorKind::InvalidData => StatusCode::BAD_REQUEST,
+            // Request body over its limit
+            ErrorKind::FileTooLarge => StatusCode::REQUEST_ENTITY_TOO_LARGE,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -14,3 +14,35 @@ pub trait HttpErrorHandler: Send + Sync + 'static {
     /// Shows a plain error code page for internal errors
     fn plain_code(&self, code: StatusCode) -> HttpResponse;
 }
+
+/// Turns specific errors into responses before they reach the [`HttpErrorHandler`]
+///
+/// Unlike with error handler, status code of the returned response is kept.
+/// Implemented for closures with the same signature as [`HttpErrorMapper::map`]
+///
+/// # Example
+/// ```
+/// # use dhttp::prelude::*;
+/// # use dhttp::reqres::res;
+/// let mut server = HttpServer::new();
+/// // Send browsers to the login page
+/// server.map_error(|_req: &HttpRequest, error: &dyn HttpError| {
+///     if error.status_code().0 != 401 { return None; }
+///     let mut res = res::redirect("/login");
+///     res.code = StatusCode::SEE_OTHER;
+///     Some(res)
+/// });
+/// ```
+pub trait HttpErrorMapper: Send + Sync + 'static {
+    /// Constructs an [`HttpResponse`] from given [`HttpError`], or returns `None` to pass it further
+    fn map(&self, req: &HttpRequest, error: &dyn HttpError) -> Option<HttpResponse>;
+}
+
+impl<F> HttpErrorMapper for F
+where
+    F: Fn(&HttpRequest, &dyn HttpError) -> Option<HttpResponse> + Send + Sync + 'static,
+{
+    fn map(&self, req: &HttpRequest, error: &dyn HttpError) -> Option<HttpResponse> {
+        self(req, error)
+    }
+}
@@ -0,0 +1,30 @@
+use crate::reqres::HttpRequest;
+use crate::core::HttpResult;
+
+/// Server-wide check that runs before the service's [`filter`](crate::core::HttpService::filter)
+///
+/// Rejected requests get the usual error response.
+/// Implemented for closures with the same signature as [`HttpGuard::check`]
+///
+/// # Example
+/// ```
+/// # use dhttp::prelude::*;
+/// let mut server = HttpServer::new();
+/// server.guard(|_route: &str, req: &HttpRequest| {
+///     if !req.cmp_header("X-Api-Key", "secret") { return Err(StatusCode::FORBIDDEN.into()); }
+///     Ok(())
+/// });
+/// ```
+pub trait HttpGuard: Send + Sync + 'static {
+    /// Checks if request can be served
+    fn check(&self, route: &str, req: &HttpRequest) -> HttpResult<()>;
+}
+
+impl<F> HttpGuard for F
+where
+    F: Fn(&str, &HttpRequest) -> HttpResult<()> + Send + Sync + 'static,
+{
+    fn check(&self, route: &str, req: &HttpRequest) -> HttpResult<()> {
+        self(route, req)
+    }
+}
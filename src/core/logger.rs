@@ -1,3 +1,6 @@
+use std::io;
+use std::net::IpAddr;
+
 use crate::reqres::{HttpRequest, HttpResponse};
 use crate::core::HttpError;
 
@@ -7,4 +10,14 @@ pub trait HttpLogger: Send + Sync + 'static {
     fn log(&self, req: &HttpRequest, res: &HttpResponse);
     /// Log an error
     fn err(&self, req: &HttpRequest, res: &HttpResponse, error: &dyn HttpError);
+    /// Log a response that was cut short (usually because client has disconnected)
+    ///
+    /// This is called after [`HttpLogger::log`] or [`HttpLogger::err`] for the same request
+    fn aborted(&self, _req: &HttpRequest, _res: &HttpResponse, _error: &io::Error) {}
+    /// Log a failed `accept()` (for example, when fd limit is exhausted)
+    fn accept_err(&self, _error: &io::Error) {}
+    /// Log an error that has closed the connection (network errors are not logged)
+    fn conn_err(&self, _addr: IpAddr, _error: &io::Error) {}
+    /// Log the start of shutdown, with the number of connections that are still open
+    fn shutdown(&self, _connections: usize) {}
 }
@@ -0,0 +1,80 @@
+use crate::reqres::HttpRequest;
+use crate::core::{HttpService, HttpServiceRaw, HttpResult, HttpRead};
+
+/// Logic that runs around a service, like logging, authentication or timing
+///
+/// Applied with [`HttpService::wrap`], the result is a service too, so it can wrap the whole
+/// [`Router`](crate::service::Router) or only one of its routes
+///
+/// # Example
+/// ```
+/// # use std::time::Instant;
+/// # use dhttp::prelude::*;
+/// # use dhttp::service::{Router, DefaultService};
+/// use dhttp::core::{HttpMiddleware, Next};
+///
+/// struct Timing;
+/// impl HttpMiddleware for Timing {
+///     async fn handle(&self, route: &str, req: &HttpRequest, body: &mut dyn HttpRead, next: Next<'_>) -> HttpResult {
+///         let start = Instant::now();
+///         let mut res = next.run(route, req, body).await?;
+///         res.add_header("Server-Timing", format!("app;dur={}", start.elapsed().as_millis()));
+///         Ok(res)
+///     }
+/// }
+///
+/// let mut router = Router::new();
+/// router.add("/", DefaultService.wrap(Timing));
+/// ```
+pub trait HttpMiddleware: Send + Sync + 'static {
+    /// Serves the request, `next` passes it on to the wrapped service
+    ///
+    /// Equivalent signature:
+    /// `async fn handle(&self, route: &str, req: &HttpRequest, body: &mut dyn HttpRead, next: Next<'_>) -> HttpResult`
+    fn handle(&self, route: &str, req: &HttpRequest, body: &mut dyn HttpRead, next: Next<'_>) -> impl Future<Output = HttpResult> + Send;
+
+    /// Checks if request is valid, by default the wrapped service decides
+    fn filter(&self, route: &str, req: &HttpRequest, next: Next<'_>) -> HttpResult<()> {
+        next.filter(route, req)
+    }
+}
+
+/// The wrapped service, given to [`HttpMiddleware`]
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    service: &'a dyn HttpServiceRaw,
+}
+
+impl Next<'_> {
+    /// Runs the wrapped service
+    pub async fn run(self, route: &str, req: &HttpRequest, body: &mut dyn HttpRead) -> HttpResult {
+        self.service.request_raw(route, req, body).await
+    }
+
+    /// Runs [`filter`](HttpService::filter) of the wrapped service
+    pub fn filter(self, route: &str, req: &HttpRequest) -> HttpResult<()> {
+        self.service.filter_raw(route, req)
+    }
+}
+
+/// Service wrapped with a middleware, created by [`HttpService::wrap`]
+pub struct Wrapped<S, M> {
+    service: S,
+    middleware: M,
+}
+
+impl<S, M> Wrapped<S, M> {
+    pub(crate) fn new(service: S, middleware: M) -> Wrapped<S, M> {
+        Wrapped { service, middleware }
+    }
+}
+
+impl<S: HttpService, M: HttpMiddleware> HttpService for Wrapped<S, M> {
+    async fn request(&self, route: &str, req: &HttpRequest, body: &mut dyn HttpRead) -> HttpResult {
+        self.middleware.handle(route, req, body, Next { service: &self.service }).await
+    }
+
+    fn filter(&self, route: &str, req: &HttpRequest) -> HttpResult<()> {
+        self.middleware.filter(route, req, Next { service: &self.service })
+    }
+}
@@ -7,7 +7,13 @@ pub use error::{HttpError, HttpErrorType};
 mod logger;
 pub use logger::HttpLogger;
 mod errorhandler;
-pub use errorhandler::HttpErrorHandler;
+pub use errorhandler::{HttpErrorHandler, HttpErrorMapper};
+mod middleware;
+pub use middleware::{HttpMiddleware, Next, Wrapped};
+mod guard;
+pub use guard::HttpGuard;
+mod progress;
+pub use progress::HttpProgress;
 pub mod connection;
 pub use connection::HttpRead;
 
@@ -0,0 +1,22 @@
+use std::io;
+
+use crate::reqres::HttpRequest;
+
+/// Observes body transfers, for example to update a UI or to kill stalled transfers
+///
+/// Only bodies that are streamed are reported. Bodies in memory are sent along with headers
+pub trait HttpProgress: Send + Sync + 'static {
+    /// Called while response body is being sent, with amount of bytes sent so far
+    ///
+    /// Returning an error aborts the transfer and closes the connection
+    fn sent(&self, _req: &HttpRequest, _bytes: u64) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called while request body is being read by the service, with amount of bytes read so far
+    ///
+    /// Returning an error fails the read
+    fn received(&self, _req: &HttpRequest, _bytes: u64) -> io::Result<()> {
+        Ok(())
+    }
+}
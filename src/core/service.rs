@@ -1,7 +1,7 @@
 use std::pin::Pin;
 
 use crate::reqres::{HttpRequest, HttpMethod, StatusCode};
-use crate::core::{HttpResult, HttpRead};
+use crate::core::{HttpResult, HttpRead, HttpMiddleware, Wrapped};
 
 /// Basic building block of your web application
 ///
@@ -18,12 +18,18 @@ pub trait HttpService: Send + Sync + 'static {
 
     /// Checks if request is valid
     ///
-    /// By default, it checks that method is [`HttpMethod::Get`] and `req.len` is 0
+    /// By default, it checks that method is [`HttpMethod::Get`] or [`HttpMethod::Head`] and there is no body
+    /// (`req.len` is 0 and it is not chunked)
     fn filter(&self, _route: &str, req: &HttpRequest) -> HttpResult<()> {
         if req.method != HttpMethod::Get && req.method != HttpMethod::Head { return Err(StatusCode::METHOD_NOT_ALLOWED.into()); }
-        if req.len > 0 { return Err(StatusCode::REQUEST_ENTITY_TOO_LARGE.into()); }
+        if req.len > 0 || req.chunked { return Err(StatusCode::REQUEST_ENTITY_TOO_LARGE.into()); }
         Ok(())
     }
+
+    /// Wraps this service with a middleware, see [`HttpMiddleware`]
+    fn wrap<M: HttpMiddleware>(self, middleware: M) -> Wrapped<Self, M> where Self: Sized {
+        Wrapped::new(self, middleware)
+    }
 }
 
 /// Dyn version of [`HttpService`]
@@ -3,12 +3,16 @@
 use std::io::{self, ErrorKind, Write};
 use std::fmt;
 use std::string::FromUtf8Error;
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
 
-use crate::reqres::{HttpRequest, HttpResponse, HttpHeader, HttpVersion, HttpMethod, HttpBody};
-use crate::core::connection::{HttpRead, HttpConnection};
+use crate::reqres::{HttpRequest, HttpResponse, HttpHeader, HttpVersion, HttpMethod, HttpBody, StatusCode};
+use crate::reqres::sse;
+use crate::core::HttpProgress;
+use crate::core::connection::{HttpRead, HttpConnection, Progress};
+use crate::util::httpdate;
+use crate::util::future::now_or_never;
 
 fn parse_ver(ver: &str) -> Option<HttpVersion> {
     let mut split = ver.strip_prefix("HTTP/")?.split('.');
@@ -23,78 +27,253 @@ fn parse_ver(ver: &str) -> Option<HttpVersion> {
 
 fn parse_header(header: &str) -> Option<HttpHeader> {
     let colon = header.find(':')?;
+    // `Transfer-Encoding : chunked` would be ignored by us, but trimmed by some proxies (RFC 9112 5.1)
+    if header[..colon].contains(|c: char| c.is_ascii_whitespace()) { return None; }
     let name = header[..colon].to_string();
     let value = header[colon+1..].trim().to_string();
     Some(HttpHeader { name, value })
 }
 
 fn split3(line: &str) -> Option<(&str, &str, &str)> {
-    let mut split = line.split_whitespace();
-    let (method, route, version) = (split.next()?, split.next()?, split.next()?);
-    if split.next().is_some() {
-        // 4th element is invalid
+    // Components are separated by exactly one SP (RFC 9112)
+    // Being lenient here makes requests look different to us and to proxies
+    let (method, rest) = line.split_once(' ')?;
+    let (route, version) = rest.split_once(' ')?;
+    let valid = |s: &str| !s.is_empty() && !s.contains(|c: char| c.is_ascii_whitespace());
+    if !valid(method) || !valid(route) || !valid(version) {
+        // empty or 4th element is invalid
         return None;
     }
     Some((method, route, version))
 }
 
-/// Reads a request from the provided stream
-pub(crate) async fn read(conn: impl HttpRead) -> Result<HttpRequest, HttpRequestError> {
-    let mut lines = conn.lines();
+/// Reads a line without its ending, keeping the original bytes in `raw`
+///
+/// Lines are limited to `max_len`, so one giant line can't take the whole headers limit
+async fn next_line(conn: &mut impl HttpRead, raw: &mut String, max_len: usize) -> Result<Option<String>, HttpRequestError> {
+    let mut line = vec![];
+    loop {
+        let buf = conn.fill_buf().await?;
+        if buf.is_empty() {
+            // Last line may not have a newline
+            if line.is_empty() { return Ok(None); }
+            break;
+        }
+
+        let (chunk, done) = match buf.iter().position(|&c| c == b'\n') {
+            Some(end) => (&buf[..=end], true),
+            None => (buf, false),
+        };
+        // +2 for \r\n
+        if line.len() + chunk.len() > max_len + 2 { return Err(HttpRequestError::HeaderTooLong); }
+        line.extend_from_slice(chunk);
+        let used = chunk.len();
+        conn.consume(used);
+        if done { break; }
+    }
+
+    let line = String::from_utf8(line)?;
+    raw.push_str(&line);
+    let line = line.strip_suffix('\n').unwrap_or(&line);
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    if line.len() > max_len { return Err(HttpRequestError::HeaderTooLong); }
+    Ok(Some(line.to_string()))
+}
+
+/// Reads a request from the provided stream, lines are limited to `max_line` bytes
+///
+/// Only the request head is read here, it is parsed by [`parse`]
+pub(crate) async fn read(mut conn: impl HttpRead, max_line: usize, strict: bool) -> Result<HttpRequest, HttpRequestError> {
+    let mut raw = String::new();
 
     // get first line
-    let first = lines.next_line().await?.ok_or(HttpRequestError::EarlyEof)?;
-    // and slice it by 3 components
-    let (method, route, version) = split3(&first).ok_or(HttpRequestError::InvalidPrelude)?;
+    let first = next_line(&mut conn, &mut raw, max_line).await
+        .map_err(|err| match err {
+            HttpRequestError::HeaderTooLong => HttpRequestError::UriTooLong,
+            err => err,
+        })?
+        .ok_or(HttpRequestError::EarlyEof)?;
+    if first.is_empty() { return Err(HttpRequestError::InvalidPrelude); }
+    // read headers
+    loop {
+        // will return if connection is shut down without \n\n
+        let line = next_line(&mut conn, &mut raw, max_line).await?.ok_or(HttpRequestError::EarlyEof)?;
+        if line.is_empty() {
+            // empty line = end of request
+            break;
+        }
+    }
+
+    parse(raw, strict)
+}
+
+/// Checks if `buf` has a whole request head, so [`read`] returns without waiting
+pub(crate) fn head_complete(buf: &[u8]) -> bool {
+    buf.windows(2).any(|w| w == b"\n\n") || buf.windows(3).any(|w| w == b"\n\r\n")
+}
+
+/// Parses a request head: request line, headers and the empty line after them
+///
+/// It has no IO, so it can be fuzzed (see `fuzz/`). With `strict`, RFC 9112 is followed to the letter:
+/// - lines end with CRLF, bare LF is rejected
+/// - method and header names are tokens
+/// - request target is in origin form (`/path`), absolute form, `*`, or authority form for `CONNECT`
+/// - version is `HTTP/x.y` with single digits
+/// - header values have no control characters except tab
+/// - `Content-Length` is not repeated
+/// - HTTP/1.1 requests have exactly one `Host`
+///
+/// Whitespace in header names is rejected in both modes, and so is `Content-Length` that isn't only digits
+/// or is repeated with different values
+pub(crate) fn parse(raw: String, strict: bool) -> Result<HttpRequest, HttpRequestError> {
+    let mut lines = raw.split_inclusive('\n').map(|line| {
+        let crlf = line.ends_with("\r\n");
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        (line.strip_suffix('\r').unwrap_or(line), crlf)
+    });
+
+    let (first, crlf) = lines.next().ok_or(HttpRequestError::EarlyEof)?;
+    // slice it by 3 components
+    let (method, route, version) = split3(first).ok_or(HttpRequestError::InvalidPrelude)?;
+    if strict && (!crlf || !is_token(method) || !valid_target(method, route)) {
+        return Err(HttpRequestError::InvalidPrelude);
+    }
     // then parse method, allocate route, parse version
     let method = HttpMethod::new(method);
     let route = route.to_string();
+    if strict && !matches!(version.as_bytes(), [b'H', b'T', b'T', b'P', b'/', b'0'..=b'9', b'.', b'0'..=b'9']) {
+        return Err(HttpRequestError::InvalidVersion);
+    }
     let version = parse_ver(version).ok_or(HttpRequestError::InvalidVersion)?;
-    // read headers
+
     let mut headers = vec![];
-    loop {
-        // will return if connection is shut down without \n\n
-        let line = lines.next_line().await?.ok_or(HttpRequestError::EarlyEof)?;
+    let mut ended = false;
+    for (line, crlf) in lines {
+        if strict && !crlf { return Err(HttpRequestError::InvalidHeader); }
         if line.is_empty() {
             // empty line = end of request
+            ended = true;
             break;
         }
-        headers.push(parse_header(&line).ok_or(HttpRequestError::InvalidHeader)?);
+        if line.starts_with([' ', '\t']) {
+            // obsolete line folding
+            return Err(HttpRequestError::InvalidHeader);
+        }
+        let header = parse_header(line).ok_or(HttpRequestError::InvalidHeader)?;
+        if strict && (!is_token(&header.name) || header.value.bytes().any(|b| b != b'\t' && (b < 0x20 || b == 0x7f))) {
+            return Err(HttpRequestError::InvalidHeader);
+        }
+        headers.push(header);
     }
+    if !ended { return Err(HttpRequestError::EarlyEof); }
 
     let addr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
-    let mut req = HttpRequest { method, route, version, headers, len: 0, addr };
+    let local_addr = SocketAddr::new(addr, 0);
+    let mut req = HttpRequest { method, route, version, headers, len: 0, chunked: false, addr, local_addr, secure: false, trusted_proxy: false, peer_certificates: None, raw, trailers: Default::default() };
+
+    if req.has_header("Transfer-Encoding") {
+        // Only chunked alone is supported, other codings would leave the body unframed
+        let mut codings = req.get_all_headers("Transfer-Encoding").flat_map(|value| value.split(',')).map(str::trim);
+        if !codings.next().is_some_and(|coding| coding.eq_ignore_ascii_case("chunked")) || codings.next().is_some() {
+            return Err(HttpRequestError::UnsupportedEncoding);
+        }
+        // With both, the request may be framed differently by a proxy in front of us
+        if req.has_header("Content-Length") {
+            return Err(HttpRequestError::InvalidLength);
+        }
+        req.chunked = true;
+    }
 
     if let Some(content_length) = req.get_header("Content-Length") {
+        // Anything a proxy could read as another length is rejected, like `+5` or `5` and `10` together
+        let mut values = req.get_all_headers("Content-Length");
+        if content_length.is_empty() || !content_length.bytes().all(|b| b.is_ascii_digit())
+            || if strict { values.count() > 1 } else { values.any(|value| value != content_length) }
+        {
+            return Err(HttpRequestError::InvalidLength);
+        }
         req.len = content_length.parse().map_err(|_| HttpRequestError::InvalidLength)?;
     }
 
+    if strict && req.version.major == 1 && req.version.minor >= 1 && req.get_all_headers("Host").count() != 1 {
+        return Err(HttpRequestError::InvalidHost);
+    }
+
     Ok(req)
 }
 
+/// `tchar` of RFC 9110
+fn is_token(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+fn valid_target(method: &str, target: &str) -> bool {
+    target.starts_with('/') || target == "*" || target.contains("://") || method == "CONNECT"
+}
+
 /// Send the request
-pub(crate) async fn send(req: &HttpRequest, res: &mut HttpResponse, conn: &mut dyn HttpConnection) -> io::Result<()> {
+///
+/// Streamed bodies are sent in chunks of up to `stream_buffer` bytes
+pub(crate) async fn send(req: &HttpRequest, res: &mut HttpResponse, conn: &mut dyn HttpConnection, progress: Option<&dyn HttpProgress>, stream_buffer: usize) -> io::Result<()> {
     let code = res.code;
     let status = code.as_str();
-    let mut buf = format!("HTTP/1.1 {code} {status}\r\n").into_bytes();
+    // We always respond with the highest version we support
+    let version = HttpVersion::HTTP_1_1;
+    let mut buf = vec![];
+
+    // Interim responses go first, in the same write. HTTP/1.0 clients don't expect them,
+    // `100 Continue` is ours and `101` is not interim
+    if req.version.is(1, 1) {
+        for (code, headers) in &res.informational {
+            if !(102..=199).contains(&code.0) { continue; }
+            write!(&mut buf, "{version} {code} {}\r\n", code.as_str()).unwrap();
+            for header in headers {
+                write!(&mut buf, "{}: {}\r\n", &header.name, &header.value).unwrap();
+            }
+            buf.extend(b"\r\n");
+        }
+    }
+
+    write!(&mut buf, "{version} {code} {status}\r\n").unwrap();
+
+    // These responses never have a body, even if service has set one
+    let no_body = matches!(code.0, 100..=199 | 204 | 304);
+    // HTTP/1.0 clients read the stream until connection is closed
+    let chunked = req.version.is(1, 1);
+    // Framing of bodies with known length is ours, stale headers from the service
+    // (e.g. length before compression) would corrupt the stream
+    let framed = !no_body && matches!(res.body, HttpBody::Bytes(_) | HttpBody::File { .. } | HttpBody::Seekable { .. } | HttpBody::Stream(_));
+    // Except the length of a stream for HEAD, which is advertised by the service (see `res::stream_with_len`)
+    let head_len = req.method == HttpMethod::Head && matches!(res.body, HttpBody::Stream(_)) && res.has_header("Content-Length");
 
     for header in &res.headers {
+        let is_length = header.name.eq_ignore_ascii_case("Content-Length");
+        if framed && ((is_length && !head_len) || header.name.eq_ignore_ascii_case("Transfer-Encoding")) {
+            continue;
+        }
         write!(&mut buf, "{}: {}\r\n", &header.name, &header.value).unwrap();
     }
 
+    // Origin servers have to send the Date, unless service did it already
+    if !res.has_header("Date") && let Some(date) = httpdate::now() {
+        write!(&mut buf, "Date: {date}\r\n").unwrap();
+    }
+
     if !res.content_type.is_empty() {
         write!(&mut buf, "Content-Type: {}\r\n", &res.content_type).unwrap();
     }
 
     match &res.body {
+        _ if no_body => {},
         HttpBody::Bytes(bytes) => write!(&mut buf, "Content-Length: {}\r\n", bytes.len()).unwrap(),
-        HttpBody::File { len, .. } => write!(&mut buf, "Content-Length: {}\r\n", len).unwrap(),
-        HttpBody::Empty | HttpBody::Sse(_) => {},
+        HttpBody::File { len, .. } | HttpBody::Seekable { len, .. } => write!(&mut buf, "Content-Length: {}\r\n", len).unwrap(),
+        HttpBody::Stream(_) if chunked && !head_len => buf.extend(b"Transfer-Encoding: chunked\r\n"),
+        HttpBody::Empty | HttpBody::Sse(_) | HttpBody::Stream(_) | HttpBody::Upgrade(_) => {},
     };
     buf.extend(b"\r\n");
 
     // Save 1 syscall by merging headers with static body
-    if let HttpBody::Bytes(bytes) = &res.body && req.method != HttpMethod::Head {
+    if let HttpBody::Bytes(bytes) = &res.body && req.method != HttpMethod::Head && !no_body {
         buf.extend(bytes);
     }
 
@@ -102,17 +281,60 @@ pub(crate) async fn send(req: &HttpRequest, res: &mut HttpResponse, conn: &mut d
     conn.write_all(&buf).await?;
 
     // Don't send body on head requests
-    if req.method == HttpMethod::Head { return Ok(()); }
+    if req.method == HttpMethod::Head || no_body { return Ok(()); }
+
+    // Streamed bodies are reported to the progress observer
+    let mut tracked;
+    let conn: &mut dyn HttpConnection = match progress {
+        Some(progress) => {
+            tracked = Progress::new(conn, req, progress);
+            &mut tracked
+        }
+        None => conn,
+    };
 
     // Now, handle the body
     match &mut res.body {
         HttpBody::Empty => {},
         HttpBody::Bytes(_) => { /* already sent with headers */ },
+        HttpBody::Upgrade(_) => { /* connection handler passes the connection */ },
         HttpBody::File { file, len } => {
             tokio::io::copy(&mut file.take(*len), conn).await?;
         }
+        HttpBody::Seekable { reader, len } => {
+            tokio::io::copy(&mut reader.take(*len), conn).await?;
+        }
+        HttpBody::Stream(stream) if chunked => {
+            // Read errors return early without the last chunk, so the client can tell that the body is cut
+            let mut chunk = vec![0; stream_buffer.max(1)];
+            let mut eof = false;
+            while !eof {
+                let mut len = stream.read(&mut chunk).await?;
+                if len == 0 { break; }
+                // Coalesce small reads, but only the ones that are ready, so nothing waits in the buffer
+                while len < chunk.len() {
+                    match now_or_never(stream.read(&mut chunk[len..])).await {
+                        Some(Ok(0)) => { eof = true; break; }
+                        Some(read) => len += read?,
+                        None => break,
+                    }
+                }
+                // Merge chunk with its framing to save syscalls
+                let mut framed = format!("{len:x}\r\n").into_bytes();
+                framed.extend(&chunk[..len]);
+                framed.extend(b"\r\n");
+                conn.write_all(&framed).await?;
+            }
+            conn.write_all(b"0\r\n\r\n").await?;
+        }
+        HttpBody::Stream(stream) => {
+            tokio::io::copy(stream, conn).await?;
+        }
         HttpBody::Sse(handler) => {
             while let Some(event) = handler.next_raw().await {
+                if event.0.len() > sse::MAX_EVENT_SIZE {
+                    return Err(io::Error::new(ErrorKind::InvalidData, "SSE event too large"));
+                }
                 conn.write_all(event.0.as_bytes()).await?;
             }
             conn.shutdown().await?;
@@ -138,10 +360,33 @@ pub(crate) enum HttpRequestError {
     InvalidPrelude,
     /// Could not parse HTTP version
     InvalidVersion,
-    /// Header line did not contain a colon
+    /// Header line did not contain a colon, had whitespace in its name, or was malformed (strict parsing only)
     InvalidHeader,
-    /// `Content-Length` header did not contain a number
+    /// `Content-Length` header was not a plain number, was repeated with different values, or came together with `Transfer-Encoding`
     InvalidLength,
+    /// Request had a `Transfer-Encoding` other than `chunked`
+    UnsupportedEncoding,
+    /// Request line was longer than its limit
+    UriTooLong,
+    /// Header line was longer than its limit
+    HeaderTooLong,
+    /// HTTP/1.1 request had no `Host` header or more than one (strict parsing only)
+    InvalidHost,
+    /// Request head did not arrive in time
+    Timeout,
+}
+
+impl HttpRequestError {
+    /// Status code to respond with
+    pub(crate) fn status_code(&self) -> StatusCode {
+        match self {
+            HttpRequestError::UnsupportedEncoding => StatusCode::NOT_IMPLEMENTED,
+            HttpRequestError::UriTooLong => StatusCode::URI_TOO_LONG,
+            HttpRequestError::HeaderTooLong => StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            HttpRequestError::Timeout => StatusCode::REQUEST_TIMEOUT,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
 }
 
 impl fmt::Display for HttpRequestError {
@@ -154,8 +399,13 @@ impl fmt::Display for HttpRequestError {
             // first line of request did not contain exactly 3 elements (method, path and version)
             HttpRequestError::InvalidPrelude => fmt.write_str("invalid prelude"),
             HttpRequestError::InvalidVersion => fmt.write_str("invalid http version"),
-            HttpRequestError::InvalidHeader => fmt.write_str("header without a colon"),
-            HttpRequestError::InvalidLength => fmt.write_str("content-length header did not contain a number"),
+            HttpRequestError::InvalidHeader => fmt.write_str("malformed header"),
+            HttpRequestError::InvalidLength => fmt.write_str("invalid content-length"),
+            HttpRequestError::UnsupportedEncoding => fmt.write_str("unsupported transfer-encoding"),
+            HttpRequestError::UriTooLong => fmt.write_str("request line too long"),
+            HttpRequestError::HeaderTooLong => fmt.write_str("header line too long"),
+            HttpRequestError::InvalidHost => fmt.write_str("missing or repeated host header"),
+            HttpRequestError::Timeout => fmt.write_str("request head timed out"),
         }
     }
 }
@@ -175,3 +425,124 @@ impl From<io::Error> for HttpRequestError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader, ReadBuf, duplex};
+    use crate::reqres::{res, HttpMethod, HttpRequest, HttpVersion};
+    use super::{split3, read, parse, send, HttpRequestError};
+    #[test]
+    fn prelude() {
+        assert_eq!(split3("GET / HTTP/1.1"), Some(("GET", "/", "HTTP/1.1")));
+        assert_eq!(split3("GET\t/\tHTTP/1.1"), None);
+        assert_eq!(split3("GET /\tHTTP/1.1"), None);
+        assert_eq!(split3("GET  / HTTP/1.1"), None);
+        assert_eq!(split3("GET / HTTP/1.1 "), None);
+        assert_eq!(split3(" GET / HTTP/1.1"), None);
+        assert_eq!(split3("GET / HTTP/1.1 extra"), None);
+        assert_eq!(split3("GET /"), None);
+    }
+
+    #[test]
+    fn strict() {
+        let strict = |raw: &str| parse(raw.to_string(), true).map(|_| ()).map_err(|err| err.to_string());
+        let lenient = |raw: &str| parse(raw.to_string(), false).is_ok();
+        let ok = "GET / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\n";
+        assert_eq!(strict(ok), Ok(()));
+        for bad in [
+            "GET / HTTP/1.1\nHost: a\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: a\n\r\n",
+            "GET / HTTP/1.1\r\nHost: a\r\n\n",
+            "GET / HTTP/1.1\r\nHost: a\x01\r\n\r\n",
+            "G(T / HTTP/1.1\r\nHost: a\r\n\r\n",
+            "GET x HTTP/1.1\r\nHost: a\r\n\r\n",
+            "GET / HTTP/01.1\r\nHost: a\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nContent-Length: 5\r\n\r\n",
+            "GET / HTTP/1.1\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n",
+        ] {
+            assert!(strict(bad).is_err(), "{bad:?}");
+            assert!(lenient(bad), "{bad:?}");
+        }
+        assert_eq!(strict("GET / HTTP/1.0\r\n\r\n"), Ok(()));
+        assert_eq!(strict("CONNECT example.com:443 HTTP/1.1\r\nHost: example.com\r\n\r\n"), Ok(()));
+        assert!(!lenient("GET / HTTP/1.1\r\nHost: a\r\n"));
+        for bad in [
+            "GET / HTTP/1.1\r\nHost : a\r\n\r\n",
+            "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding : chunked\r\n\r\n",
+            "POST / HTTP/1.1\r\nHost: a\r\nContent-Length\t: 5\r\n\r\n",
+        ] {
+            assert_eq!(strict(bad), Err(HttpRequestError::InvalidHeader.to_string()), "{bad:?}");
+            assert!(!lenient(bad), "{bad:?}");
+        }
+        for bad in [
+            "GET / HTTP/1.1\r\nHost: a\r\nContent-Length: +5\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\nContent-Length: 10\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: a\r\nContent-Length: 5, 5\r\n\r\n",
+        ] {
+            assert_eq!(strict(bad), Err(HttpRequestError::InvalidLength.to_string()), "{bad:?}");
+            assert!(!lenient(bad), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn raw_headers() {
+        let rt = crate::server::tokio_rt().unwrap();
+        let raw = "GET / HTTP/1.1\r\nx-LOWER:  value \r\nHost: a\n\r\n";
+        let req = rt.block_on(read(raw.as_bytes(), 1024, false)).unwrap();
+        assert_eq!(req.raw, raw);
+        assert_eq!(req.headers[0].name, "x-LOWER");
+        assert_eq!(req.headers[0].value, "value");
+        assert!(rt.block_on(read(&b"GET / HTTP/1.1\r\nA: b\r\n c\r\n\r\n"[..], 1024, false)).is_err());
+    }
+
+    #[test]
+    fn line_limit() {
+        let rt = crate::server::tokio_rt().unwrap();
+        let req = format!("GET / HTTP/1.1\r\nA: {}\r\n\r\n", "a".repeat(100));
+        assert!(rt.block_on(read(req.as_bytes(), 103, false)).is_ok());
+        assert!(matches!(rt.block_on(read(req.as_bytes(), 102, false)), Err(HttpRequestError::HeaderTooLong)));
+        let req = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(100));
+        assert!(matches!(rt.block_on(read(req.as_bytes(), 50, false)), Err(HttpRequestError::UriTooLong)));
+    }
+
+    /// Sends `abc`, then fails
+    struct Broken(bool);
+    impl AsyncRead for Broken {
+        fn poll_read(mut self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+            if self.0 { return Poll::Ready(Err(io::Error::other("broken"))); }
+            self.0 = true;
+            buf.put_slice(b"abc");
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn send_stream(method: HttpMethod) -> (io::Result<()>, String) {
+        crate::server::tokio_rt().unwrap().block_on(async {
+            let (mut client, conn) = duplex(65536);
+            let mut conn = BufReader::new(conn);
+            let req = HttpRequest { method, version: HttpVersion::HTTP_1_1, ..Default::default() };
+            let result = send(&req, &mut res::stream("text/plain", Broken(false)), &mut conn, None, 16).await;
+            conn.shutdown().await.unwrap();
+            let mut out = String::new();
+            client.read_to_string(&mut out).await.unwrap();
+            (result, out)
+        })
+    }
+
+    #[test]
+    fn stream_errors() {
+        let (result, out) = send_stream(HttpMethod::Get);
+        assert!(result.is_err());
+        assert!(out.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(!out.ends_with("0\r\n\r\n"));
+
+        let (result, out) = send_stream(HttpMethod::Head);
+        assert!(result.is_ok());
+        assert!(out.ends_with("\r\n\r\n"));
+        assert!(!out.contains("abc"));
+    }
+}
@@ -8,5 +8,23 @@ pub mod service;
 pub mod prelude;
 pub mod server;
 pub mod util;
+#[cfg(feature = "tls")]
+pub mod tls;
 
-pub use server::{tokio_rt, serve_tcp};
+pub use server::{tokio_rt, serve_tcp, serve_tcp_with_shutdown, ServeOutcome};
+#[cfg(unix)]
+pub use server::{serve_unix, serve_unix_with_shutdown};
+#[cfg(feature = "tls")]
+pub use tls::{serve_tls, serve_tls_with_shutdown};
+
+/// Entry points of the fuzz targets in `fuzz/`, not a stable API
+#[doc(hidden)]
+pub mod fuzz {
+    use crate::reqres::HttpRequest;
+
+    /// Parses a request head the way the server does, `None` if it would be rejected
+    pub fn parse_request(data: &[u8], strict: bool) -> Option<HttpRequest> {
+        let raw = String::from_utf8(data.to_vec()).ok()?;
+        crate::h1::parse(raw, strict).ok()
+    }
+}
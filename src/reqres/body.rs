@@ -1,9 +1,14 @@
 use std::fmt;
 
 use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncSeek};
 
 use crate::util::escape;
 use crate::reqres::sse::HttpSseRaw;
+use crate::reqres::upgrade::HttpUpgradeRaw;
+
+// Longer bodies are only shown with their length
+const DEBUG_MAX_LEN: usize = 256;
 
 /// Body of the response
 #[non_exhaustive]
@@ -14,21 +19,46 @@ pub enum HttpBody {
     Bytes(Vec<u8>),
     /// File handle to read
     File { file: File, len: u64 },
+    /// Any seekable reader, `len` bytes are sent from its current position
+    Seekable { reader: Box<dyn SeekableRead>, len: u64 },
     /// Server sent events
     Sse(Box<dyn HttpSseRaw>),
+    /// Stream of unknown length, sent with chunked encoding
+    Stream(Box<dyn AsyncRead + Send + Unpin>),
+    /// Connection is handed over to another protocol
+    Upgrade(Box<dyn HttpUpgradeRaw>),
 }
 
 impl fmt::Debug for HttpBody {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             HttpBody::Empty => fmt.write_str("HttpBody::Empty"),
+            HttpBody::Bytes(v) if v.len() > DEBUG_MAX_LEN => write!(fmt, "HttpBody::Bytes(<{} bytes>)", v.len()),
             HttpBody::Bytes(v) => write!(fmt, r#"HttpBody::Bytes(b"{}")"#, escape::to_utf8(v)),
             HttpBody::File { file, len } => fmt.debug_struct("HttpBody::File").field("file", file).field("len", len).finish(),
+            HttpBody::Seekable { len, .. } => fmt.debug_struct("HttpBody::Seekable").field("len", len).finish_non_exhaustive(),
             HttpBody::Sse(_) => fmt.write_str("HttpBody::Sse(..)"),
+            HttpBody::Stream(_) => fmt.write_str("HttpBody::Stream(..)"),
+            HttpBody::Upgrade(_) => fmt.write_str("HttpBody::Upgrade(..)"),
         }
     }
 }
 
+impl HttpBody {
+    /// Checks if the body is produced while being sent (SSE, streams and upgrades)
+    ///
+    /// Middlewares must not buffer these, an SSE stream never ends and buffering it would hang the response.
+    /// They are either passed through untouched (caching), or transformed as they are read without waiting
+    /// for more, like [`Gzip`](crate::service::Gzip) does with streams. SSE and upgrades are always left as is
+    pub fn is_streaming(&self) -> bool {
+        matches!(self, HttpBody::Sse(_) | HttpBody::Stream(_) | HttpBody::Upgrade(_))
+    }
+}
+
+/// Reader that supports seeking, used for byte ranges (see [`res::seekable`](crate::reqres::res::seekable))
+pub trait SeekableRead: AsyncRead + AsyncSeek + Send + Unpin {}
+impl<T: AsyncRead + AsyncSeek + Send + Unpin> SeekableRead for T {}
+
 impl From<Vec<u8>> for HttpBody {
     fn from(v: Vec<u8>) -> HttpBody {
         HttpBody::Bytes(v)
@@ -46,3 +76,24 @@ impl From<&str> for HttpBody {
         HttpBody::Bytes(s.to_string().into_bytes())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::reqres::{res, HttpBody};
+    use crate::reqres::sse::{HttpSse, HttpSseEvent};
+
+    struct Ticks;
+    impl HttpSse for Ticks {
+        async fn next(&mut self) -> Option<HttpSseEvent> {
+            None
+        }
+    }
+
+    #[test]
+    fn is_streaming() {
+        assert!(res::sse(Ticks).body.is_streaming());
+        assert!(res::stream("text/plain", &b"data"[..]).body.is_streaming());
+        assert!(!res::text("data").body.is_streaming());
+        assert!(!HttpBody::Empty.is_streaming());
+    }
+}
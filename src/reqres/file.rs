@@ -3,26 +3,33 @@ use std::path::Path;
 use std::ffi::OsStr;
 use std::collections::HashMap;
 use std::sync::LazyLock;
-use std::time::UNIX_EPOCH;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use tokio::io::AsyncSeekExt;
 use tokio::fs::File;
 
 use crate::core::HttpResult;
-use crate::reqres::{HttpRequest, HttpResponse, HttpHeader, HttpBody, StatusCode};
+use crate::reqres::{res, HttpRequest, HttpResponse, HttpHeader, HttpBody, HttpMethod, StatusCode, SeekableRead};
+use crate::reqres::range::{self, RangeError};
 use crate::util::httpdate;
 
 /// Responds with a file
+///
+/// For HEAD requests, file is only opened to get its metadata. Pipes and devices can't seek
+/// and have no length, so they are streamed with `Accept-Ranges: none`.
+/// Outside of async code, read the file yourself and respond with [`cacheable`]
 pub async fn file(req: &HttpRequest, name: &Path) -> HttpResult {
     let mut file = File::open(name).await?;
     let metadata = file.metadata().await?;
-    let mut len = metadata.len();
+    let len = metadata.len();
 
-    // becomes PARTIAL_CONTENT if range was served
-    let mut code = StatusCode::OK;
     let content_type = get_content_type(name.extension()).unwrap_or_default().to_string();
+    if !metadata.is_file() {
+        let mut res = res::stream(content_type, file);
+        res.add_header("Accept-Ranges", "none");
+        return Ok(res);
+    }
     let mut headers = vec![];
-    let mut body;
 
     // Last-Modified
     let time = metadata.modified().ok();
@@ -37,52 +44,106 @@ pub async fn file(req: &HttpRequest, name: &Path) -> HttpResult {
         headers.push(HttpHeader { name: "Date".to_string(), value: date });
     }
 
-    // Advertise byte ranges support
-    headers.push(HttpHeader {
-        name: "Accept-Ranges".to_string(),
-        value: "bytes".to_string(),
-    });
+    // Checked before the range, 304 has no Content-Range and can't be a 416
+    if not_modified_since(req, time) {
+        return Ok(HttpResponse { code: StatusCode::NOT_MODIFIED, headers, body: HttpBody::Empty, content_type, informational: vec![], background: vec![] });
+    }
 
-    // Parse byte range request
-    if let Some(range) = req.get_header("Range") {
-        if let Some((start, mut end)) = parse_range(range) && start <= len && start <= end {
-            end = end.min(len);
+    let (code, start, len) = apply_range(req, len, &mut headers)?;
+    // Body is not sent for HEAD, only headers are needed
+    if start != 0 && req.method != HttpMethod::Head {
+        file.seek(SeekFrom::Start(start)).await?;
+    }
 
-            headers.push(HttpHeader {
-                name: "Content-Range".to_string(),
-                value: format!("bytes {start}-{end}/{len}"),
-            });
+    Ok(HttpResponse { code, headers, body: HttpBody::File { file, len }, content_type, informational: vec![], background: vec![] })
+}
 
-            file.seek(SeekFrom::Start(start)).await?;
-            len = end - start + 1;
-            code = StatusCode::PARTIAL_CONTENT;
-        } else {
-            // we have to set Content-Range in case of error too but errors can't have headers in dhttp
-            return Err(StatusCode::RANGE_NOT_SATISFIABLE.into());
-        }
+/// Responds with content that is already in memory, the same way as [`file`] does
+///
+/// It gets an ETag from the body hash and `Last-Modified` from `modified`, so clients can revalidate it
+/// with `If-None-Match` or `If-Modified-Since`, and byte ranges are served too
+/// ```
+/// # use dhttp::reqres::{res, HttpRequest};
+/// # let req = HttpRequest::default();
+/// let res = res::cacheable(&req, "text/csv", b"id,name\n1,Alice\n".to_vec(), None).unwrap();
+/// assert!(res.get_header("ETag").is_some());
+/// ```
+pub fn cacheable(req: &HttpRequest, content_type: impl Into<String>, mut body: Vec<u8>, modified: Option<SystemTime>) -> HttpResult {
+    let content_type = content_type.into();
+    let mut headers = vec![];
+    if let Some(value) = modified.and_then(httpdate::from_systime) {
+        headers.push(HttpHeader { name: "Last-Modified".to_string(), value });
+    }
+    let etag = res::etag(&body);
+    headers.push(HttpHeader { name: "ETag".to_string(), value: etag.clone() });
+
+    // If-None-Match wins over If-Modified-Since
+    let not_modified = match req.get_header("If-None-Match") {
+        Some(header) => res::etag_matches(header, &etag),
+        None => not_modified_since(req, modified),
+    };
+    if not_modified {
+        return Ok(HttpResponse { code: StatusCode::NOT_MODIFIED, headers, body: HttpBody::Empty, content_type, informational: vec![], background: vec![] });
     }
 
-    body = HttpBody::File { file, len };
+    let (code, start, len) = apply_range(req, body.len() as u64, &mut headers)?;
+    body.truncate((start + len) as usize);
+    body.drain(..start as usize);
+    Ok(HttpResponse { code, headers, body: HttpBody::Bytes(body), content_type, informational: vec![], background: vec![] })
+}
 
+/// Checks `If-Modified-Since` against the modification time
+fn not_modified_since(req: &HttpRequest, modified: Option<SystemTime>) -> bool {
     // If-Modified-Since🐛🐛🐛
-    if let Some(time) = time
+    if let Some(time) = modified
         && let Some(time) = time.duration_since(UNIX_EPOCH).ok()
         && let Some(if_modified_since) = req.get_header("If-Modified-Since")
         && let Some(parsed) = httpdate::parse(if_modified_since)
-        && parsed >= time.as_secs() as i64
     {
-        code = StatusCode::NOT_MODIFIED;
-        body = HttpBody::Empty;
+        parsed >= time.as_secs() as i64
+    } else {
+        false
+    }
+}
+
+/// Responds with a seekable reader, serving byte ranges just like [`file`]
+///
+/// Length is found by seeking to the end, so the reader can be at any position
+pub async fn seekable(req: &HttpRequest, content_type: impl Into<String>, mut reader: impl SeekableRead + 'static) -> HttpResult {
+    let len = reader.seek(SeekFrom::End(0)).await?;
+
+    let mut headers = vec![];
+    let (code, start, len) = apply_range(req, len, &mut headers)?;
+    if req.method != HttpMethod::Head {
+        reader.seek(SeekFrom::Start(start)).await?;
     }
 
-    Ok(HttpResponse { code, headers, body, content_type })
+    let body = HttpBody::Seekable { reader: Box::new(reader), len };
+    Ok(HttpResponse { code, headers, body, content_type: content_type.into(), informational: vec![], background: vec![] })
 }
 
-fn parse_range(range: &str) -> Option<(u64, u64)> {
-    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
-    let start = if start.is_empty() { 0 } else { start.parse().ok()? };
-    let end = if end.is_empty() { u64::MAX } else { end.parse().ok()? };
-    Some((start, end))
+/// Applies the `Range` header to a body of `len` bytes, returns code, start and length to send
+fn apply_range(req: &HttpRequest, len: u64, headers: &mut Vec<HttpHeader>) -> HttpResult<(StatusCode, u64, u64)> {
+    // Advertise byte ranges support
+    headers.push(HttpHeader {
+        name: "Accept-Ranges".to_string(),
+        value: "bytes".to_string(),
+    });
+
+    // Multiple ranges would need a multipart body, so they are ignored, just like invalid ones
+    match req.get_header("Range").map(|header| range::parse(header, len)) {
+        Some(Ok(ranges)) if ranges.len() == 1 => {
+            let range = ranges[0];
+            headers.push(HttpHeader {
+                name: "Content-Range".to_string(),
+                value: range.content_range(len),
+            });
+            Ok((StatusCode::PARTIAL_CONTENT, range.start, range.size()))
+        }
+        // we have to set Content-Range in case of error too but errors can't have headers in dhttp
+        Some(Err(err @ RangeError::Unsatisfiable)) => Err(err.into()),
+        _ => Ok((StatusCode::OK, 0, len)),
+    }
 }
 
 // This is only for files loaded/previewed by web browser
@@ -124,7 +185,7 @@ static CONTENT_TYPES: LazyLock<HashMap<&'static OsStr, &'static str>> = LazyLock
     (os!("pdf"), "application/pdf"),
 ]));
 
-fn get_content_type(ext: Option<&OsStr>) -> Option<&'static str> {
+pub(crate) fn get_content_type(ext: Option<&OsStr>) -> Option<&'static str> {
     CONTENT_TYPES.get(ext?.to_ascii_lowercase().as_os_str()).copied()
 }
 
@@ -132,3 +193,61 @@ macro_rules! os {
     ($s:literal) => { OsStr::new($s) }
 }
 use os;
+
+#[cfg(test)]
+mod tests {
+    use super::{file, cacheable};
+    use crate::reqres::{header, HttpBody, HttpRequest};
+
+    #[cfg(unix)]
+    #[test]
+    fn device_ignores_ranges() {
+        let req = HttpRequest { headers: vec![header("Range", "bytes=0-9")], ..Default::default() };
+        let res = crate::server::tokio_rt().unwrap().block_on(file(&req, "/dev/null".as_ref())).unwrap();
+        assert_eq!(res.code.0, 200);
+        assert_eq!(res.get_header("Accept-Ranges"), Some("none"));
+        assert!(matches!(res.body, HttpBody::Stream(_)));
+    }
+
+    #[test]
+    fn cacheable_ranges() {
+        let body = b"0123456789".to_vec();
+        let res = cacheable(&HttpRequest::default(), "text/plain", body.clone(), None).unwrap();
+        let etag = res.get_header("ETag").unwrap().to_string();
+
+        let req = HttpRequest { headers: vec![header("Range", "bytes=2-4")], ..Default::default() };
+        let res = cacheable(&req, "text/plain", body.clone(), None).unwrap();
+        assert_eq!(res.code.0, 206);
+        assert!(matches!(res.body, HttpBody::Bytes(ref bytes) if bytes == b"234"));
+
+        let req = HttpRequest { headers: vec![header("If-None-Match", &etag)], ..Default::default() };
+        assert_eq!(cacheable(&req, "text/plain", body.clone(), None).unwrap().code.0, 304);
+
+        // Validators go first, even an unsatisfiable range gets a bare 304
+        let req = HttpRequest { headers: vec![header("If-None-Match", &etag), header("Range", "bytes=20-30")], ..Default::default() };
+        let res = cacheable(&req, "text/plain", body, None).unwrap();
+        assert_eq!(res.code.0, 304);
+        assert!(res.get_header("Content-Range").is_none());
+    }
+
+    #[test]
+    fn file_not_modified_before_range() {
+        let path = std::env::temp_dir().join(format!("dhttp-file-test-{}", std::process::id()));
+        std::fs::write(&path, b"0123456789").unwrap();
+        let rt = crate::server::tokio_rt().unwrap();
+
+        let since = header("If-Modified-Since", "Fri, 01 Jan 2100 00:00:00 GMT");
+        for range in ["bytes=2-4", "bytes=20-30"] {
+            let req = HttpRequest { headers: vec![since.clone(), header("Range", range)], ..Default::default() };
+            let res = rt.block_on(file(&req, &path)).unwrap();
+            assert_eq!(res.code.0, 304);
+            assert!(res.get_header("Content-Range").is_none());
+        }
+
+        let req = HttpRequest { headers: vec![header("Range", "bytes=2-4")], ..Default::default() };
+        let res = rt.block_on(file(&req, &path)).unwrap();
+        assert_eq!(res.code.0, 206);
+        assert_eq!(res.get_header("Content-Range"), Some("bytes 2-4/10"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,66 @@
+//! JSON request bodies (requires `serde` feature)
+//!
+//! # Example
+//! ```
+//! # use dhttp::prelude::*;
+//! # use dhttp::reqres::res;
+//! use dhttp::reqres::json;
+//!
+//! #[derive(serde::Deserialize)]
+//! struct Login {
+//!     user: String,
+//! }
+//!
+//! async fn login(_route: &str, req: &HttpRequest, body: &mut dyn HttpRead) -> HttpResult {
+//!     let login: Login = json::read_json(req, body, 4096).await?;
+//!     Ok(res::text(format!("Welcome, {}!\n", login.user)))
+//! }
+//! ```
+
+use std::fmt;
+use std::error::Error;
+
+use serde::de::DeserializeOwned;
+
+use crate::core::{HttpError, HttpRead, HttpResult};
+use crate::core::connection::read_to_bytes;
+use crate::reqres::{HttpRequest, StatusCode};
+
+/// Reads and deserializes a JSON body of up to `limit` bytes
+///
+/// # Errors
+/// - [`StatusCode::UNSUPPORTED_MEDIA_TYPE`] if `Content-Type` is not `application/json` (or `+json`)
+/// - [`StatusCode::REQUEST_ENTITY_TOO_LARGE`] if the body is longer than `limit`
+/// - [`JsonError`] (`400 Bad request`) if it could not be deserialized
+pub async fn read_json<T: DeserializeOwned>(req: &HttpRequest, body: &mut dyn HttpRead, limit: u64) -> HttpResult<T> {
+    let content_type = req.get_header("Content-Type").unwrap_or_default();
+    let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    if mime != "application/json" && !mime.ends_with("+json") {
+        return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE.into());
+    }
+
+    let data = read_to_bytes(body, limit).await?;
+    serde_json::from_slice(&data).map_err(|err| JsonError(err).into())
+}
+
+/// Body was not valid JSON or didn't match the expected structure
+#[derive(Debug)]
+pub struct JsonError(pub serde_json::Error);
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "invalid JSON: {}", self.0)
+    }
+}
+
+impl Error for JsonError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+impl HttpError for JsonError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
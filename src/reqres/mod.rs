@@ -1,18 +1,25 @@
 //! Request, response, status code, and their components
 
 mod status_code;
-pub use status_code::StatusCode;
+pub use status_code::{StatusCode, InvalidStatusCode};
 mod req;
 pub use req::{HttpRequest, HttpVersion, HttpMethod};
 mod body;
-pub use body::HttpBody;
+pub use body::{HttpBody, SeekableRead};
 
 pub mod res;
 pub use res::HttpResponse;
 
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod multipart;
+pub mod params;
+pub mod range;
 pub mod sse;
+pub mod upgrade;
+pub mod websocket;
 
-mod file;
+pub(crate) mod file;
 
 use std::fmt;
 
@@ -23,6 +30,12 @@ pub struct HttpHeader {
     pub value: String,
 }
 
+/// Shorthand for headers in tests
+#[cfg(test)]
+pub(crate) fn header(name: &str, value: &str) -> HttpHeader {
+    HttpHeader { name: name.to_string(), value: value.to_string() }
+}
+
 impl fmt::Debug for HttpHeader {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(fmt, "{}: {}", self.name, self.value)
@@ -0,0 +1,111 @@
+//! Multipart responses (`multipart/mixed`, `multipart/byteranges`, ...)
+//! # Example
+//! ```
+//! use dhttp::reqres::HttpHeader;
+//! use dhttp::reqres::multipart::MultipartResponse;
+//!
+//! let mut multipart = MultipartResponse::new("mixed");
+//! multipart.part(vec![HttpHeader { name: "Content-Type".to_string(), value: "text/plain".to_string() }], &b"hello"[..]);
+//! multipart.part(vec![], &b"world"[..]);
+//! let res = multipart.into_response();
+//! ```
+
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, RandomState};
+use std::io::{self, Cursor};
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::reqres::{res, HttpHeader, HttpResponse};
+
+type Reader = Box<dyn AsyncRead + Send + Unpin>;
+
+/// Builder of a multipart response, parts are streamed one after another
+pub struct MultipartResponse {
+    subtype: String,
+    boundary: String,
+    parts: VecDeque<Reader>,
+}
+
+impl MultipartResponse {
+    /// Creates an empty response of `multipart/<subtype>` with a random boundary
+    pub fn new(subtype: &str) -> MultipartResponse {
+        MultipartResponse { subtype: subtype.to_string(), boundary: boundary(), parts: VecDeque::new() }
+    }
+
+    /// Boundary between the parts
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// Adds a part with its headers and body
+    pub fn part(&mut self, headers: Vec<HttpHeader>, body: impl AsyncRead + Send + Unpin + 'static) -> &mut Self {
+        let mut head = format!("--{}\r\n", self.boundary);
+        for header in headers {
+            // Newlines would end the part headers early
+            let value = header.value.replace(['\r', '\n'], " ");
+            head.push_str(&format!("{}: {}\r\n", header.name, value));
+        }
+        head.push_str("\r\n");
+        self.parts.push_back(Box::new(Cursor::new(head.into_bytes())));
+        self.parts.push_back(Box::new(body));
+        self.parts.push_back(Box::new(&b"\r\n"[..]));
+        self
+    }
+
+    /// Finishes the response, it is sent as a stream
+    pub fn into_response(mut self) -> HttpResponse {
+        let end = format!("--{}--\r\n", self.boundary);
+        self.parts.push_back(Box::new(Cursor::new(end.into_bytes())));
+        let content_type = format!("multipart/{}; boundary={}", self.subtype, self.boundary);
+        res::stream(content_type, Concat(self.parts))
+    }
+}
+
+/// 128 random bits in hex, which are never a part of any body in practice
+fn boundary() -> String {
+    let random = || RandomState::new().hash_one(());
+    format!("{:016x}{:016x}", random(), random())
+}
+
+/// Reads the readers one after another
+struct Concat(VecDeque<Reader>);
+
+impl AsyncRead for Concat {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        while let Some(reader) = self.0.front_mut() {
+            let before = buf.filled().len();
+            ready!(Pin::new(reader).poll_read(cx, buf))?;
+            if buf.filled().len() > before || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+            // this one is done
+            self.0.pop_front();
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+    use super::MultipartResponse;
+    use crate::reqres::{header, HttpBody};
+
+    #[test]
+    fn parts() {
+        let mut multipart = MultipartResponse::new("mixed");
+        let boundary = multipart.boundary().to_string();
+        multipart.part(vec![header("Content-Type", "text/plain\r\nX-Injected: 1")], &b"hello"[..]).part(vec![], &b""[..]);
+        let res = multipart.into_response();
+        assert_eq!(res.content_type, format!("multipart/mixed; boundary={boundary}"));
+
+        let HttpBody::Stream(mut stream) = res.body else { panic!("not a stream") };
+        let mut out = String::new();
+        crate::server::tokio_rt().unwrap().block_on(stream.read_to_string(&mut out)).unwrap();
+        let expected = format!("--{boundary}\r\nContent-Type: text/plain  X-Injected: 1\r\n\r\nhello\r\n--{boundary}\r\n\r\n\r\n--{boundary}--\r\n");
+        assert_eq!(out, expected);
+    }
+}
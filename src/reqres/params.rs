@@ -0,0 +1,126 @@
+//! Query string and form parameters (`application/x-www-form-urlencoded`)
+//!
+//! # Example
+//! ```
+//! # use dhttp::prelude::*;
+//! # use dhttp::reqres::res;
+//! use dhttp::reqres::params::Params;
+//!
+//! // Works both for `GET /greet?name=Bob` and a form posted to `/greet`
+//! async fn greet(_route: &str, req: &HttpRequest, body: &mut dyn HttpRead) -> HttpResult {
+//!     let params = Params::from_request(req, body, 65536).await?;
+//!     let name = params.get("name").unwrap_or("stranger");
+//!     Ok(res::text(format!("Hello, {name}!\n")))
+//! }
+//! ```
+
+use crate::core::{HttpRead, HttpResult};
+use crate::core::connection::read_to_bytes;
+use crate::reqres::HttpRequest;
+
+/// Decoded `name=value` pairs, in the order they were received
+#[derive(Debug, Clone, Default)]
+pub struct Params {
+    pairs: Vec<(String, String)>,
+}
+
+impl Params {
+    /// Parses `a=1&b=2`, `+` is decoded as a space
+    ///
+    /// Names without `=` get an empty value, empty pairs (`a=1&&b=2`) are skipped
+    pub fn parse(s: &str) -> Params {
+        let pairs = s.split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (decode(name), decode(value))
+            })
+            .collect();
+        Params { pairs }
+    }
+
+    /// Parameters from the query string of the route
+    pub fn query(req: &HttpRequest) -> Params {
+        match req.route.split_once('?') {
+            Some((_, query)) => Params::parse(query),
+            None => Params::default(),
+        }
+    }
+
+    /// Parameters from the form body, if it is `application/x-www-form-urlencoded`
+    ///
+    /// Other bodies are left unread and give no parameters.
+    /// Bodies longer than `max_len` fail with `413 Request entity too large`
+    pub async fn form(req: &HttpRequest, body: &mut dyn HttpRead, max_len: u64) -> HttpResult<Params> {
+        let content_type = req.get_header("Content-Type").unwrap_or_default();
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        if !mime.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+            return Ok(Params::default());
+        }
+
+        let data = read_to_bytes(body, max_len).await?;
+        Ok(Params::parse(&String::from_utf8_lossy(&data)))
+    }
+
+    /// Form and query parameters together, see [`Params::form`]
+    ///
+    /// Form comes first, so if both have the same name, [`get`](Params::get) returns the value from the form.
+    /// [`get_all`](Params::get_all) returns both
+    pub async fn from_request(req: &HttpRequest, body: &mut dyn HttpRead, max_len: u64) -> HttpResult<Params> {
+        let mut params = Params::form(req, body, max_len).await?;
+        params.pairs.extend(Params::query(req).pairs);
+        Ok(params)
+    }
+
+    /// Value of the first parameter with this name
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.get_all(name).next()
+    }
+
+    /// Values of all parameters with this name, like `tag=a&tag=b`
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.pairs.iter().filter(move |(n, _)| n == name).map(|(_, value)| value.as_str())
+    }
+
+    /// All `(name, value)` pairs
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+fn decode(s: &str) -> String {
+    let s = s.replace('+', " ");
+    String::from_utf8_lossy(&percent_encoding_lite::decode(&s)).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Params;
+    use crate::reqres::{header, HttpRequest};
+
+    #[test]
+    fn parse() {
+        let params = Params::parse("a=1&b=hello+world&c=%2B%26&&d&a=2");
+        assert_eq!(params.get("a"), Some("1"));
+        assert_eq!(params.get_all("a").collect::<Vec<_>>(), ["1", "2"]);
+        assert_eq!(params.get("b"), Some("hello world"));
+        assert_eq!(params.get("c"), Some("+&"));
+        assert_eq!(params.get("d"), Some(""));
+        assert_eq!(params.get("e"), None);
+        assert_eq!(params.iter().count(), 5);
+    }
+
+    #[test]
+    fn from_request() {
+        let headers = vec![header("Content-Type", "application/x-www-form-urlencoded; charset=utf-8")];
+        let req = HttpRequest { route: "/form?a=query&b=query".to_string(), headers, ..Default::default() };
+        let mut body = &b"a=form"[..];
+        let params = crate::server::tokio_rt().unwrap().block_on(Params::from_request(&req, &mut body, 1024)).unwrap();
+        assert_eq!(params.get("a"), Some("form"));
+        assert_eq!(params.get_all("a").collect::<Vec<_>>(), ["form", "query"]);
+        assert_eq!(params.get("b"), Some("query"));
+
+        let mut body = &b"a=form"[..];
+        assert!(crate::server::tokio_rt().unwrap().block_on(Params::form(&req, &mut body, 5)).is_err());
+    }
+}
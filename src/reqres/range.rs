@@ -0,0 +1,157 @@
+//! `Range` header parsing
+//!
+//! Use it to serve `206 Partial content` from your own seekable content,
+//! [`res::file`](crate::reqres::res::file) and [`res::seekable`](crate::reqres::res::seekable) already do it
+
+use std::fmt;
+use std::error::Error;
+
+use crate::core::HttpError;
+use crate::reqres::StatusCode;
+
+/// Range of bytes, both ends are inclusive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    /// Number of bytes in this range
+    pub fn size(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Value for the `Content-Range` header
+    pub fn content_range(&self, total_len: u64) -> String {
+        format!("bytes {}-{}/{total_len}", self.start, self.end)
+    }
+}
+
+/// Parses the `Range` header for content of `total_len` bytes
+///
+/// Returned ranges are sorted, clamped to the content and merged when they overlap or touch.
+/// Ranges which start past the end are skipped, and if none are left, it fails with [`RangeError::Unsatisfiable`]
+/// ```
+/// # use dhttp::reqres::range::{self, ByteRange};
+/// assert_eq!(range::parse("bytes=-5", 100), Ok(vec![ByteRange { start: 95, end: 99 }]));
+/// ```
+pub fn parse(header: &str, total_len: u64) -> Result<Vec<ByteRange>, RangeError> {
+    let (unit, specs) = header.split_once('=').ok_or(RangeError::Invalid)?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") { return Err(RangeError::Invalid); }
+
+    let mut ranges = vec![];
+    for spec in specs.split(',').map(str::trim) {
+        let (start, end) = spec.split_once('-').ok_or(RangeError::Invalid)?;
+        let range = match (start.is_empty(), end.is_empty()) {
+            // `-N` is the last N bytes
+            (true, false) => {
+                let suffix: u64 = number(end)?;
+                if suffix == 0 || total_len == 0 { continue; }
+                ByteRange { start: total_len.saturating_sub(suffix), end: total_len - 1 }
+            }
+            // `N-` is everything from N
+            (false, true) => {
+                let start = number(start)?;
+                if start >= total_len { continue; }
+                ByteRange { start, end: total_len - 1 }
+            }
+            (false, false) => {
+                let (start, end) = (number(start)?, number(end)?);
+                if start > end { return Err(RangeError::Invalid); }
+                if start >= total_len { continue; }
+                ByteRange { start, end: end.min(total_len - 1) }
+            }
+            (true, true) => return Err(RangeError::Invalid),
+        };
+        ranges.push(range);
+    }
+
+    if ranges.is_empty() { return Err(RangeError::Unsatisfiable); }
+
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<ByteRange> = vec![];
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    Ok(merged)
+}
+
+fn number(s: &str) -> Result<u64, RangeError> {
+    // parse() would also accept `+`
+    if !s.bytes().all(|b| b.is_ascii_digit()) { return Err(RangeError::Invalid); }
+    s.parse().map_err(|_| RangeError::Invalid)
+}
+
+/// Error when parsing the `Range` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeError {
+    /// Header is malformed, it should be ignored and full content sent
+    Invalid,
+    /// No range overlaps with the content (`416 Range not satisfiable`)
+    Unsatisfiable,
+}
+
+impl fmt::Display for RangeError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RangeError::Invalid => fmt.write_str("invalid range"),
+            RangeError::Unsatisfiable => fmt.write_str("range not satisfiable"),
+        }
+    }
+}
+
+impl Error for RangeError {}
+
+impl HttpError for RangeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            RangeError::Invalid => StatusCode::BAD_REQUEST,
+            RangeError::Unsatisfiable => StatusCode::RANGE_NOT_SATISFIABLE,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, ByteRange, RangeError};
+
+    fn r(start: u64, end: u64) -> ByteRange {
+        ByteRange { start, end }
+    }
+
+    #[test]
+    fn single() {
+        assert_eq!(parse("bytes=0-9", 100), Ok(vec![r(0, 9)]));
+        assert_eq!(parse("bytes=10-", 100), Ok(vec![r(10, 99)]));
+        assert_eq!(parse("bytes=-10", 100), Ok(vec![r(90, 99)]));
+        assert_eq!(parse("bytes=-1000", 100), Ok(vec![r(0, 99)]));
+        assert_eq!(parse("bytes=50-1000", 100), Ok(vec![r(50, 99)]));
+        assert_eq!(parse("BYTES=0-0", 100), Ok(vec![r(0, 0)]));
+    }
+
+    #[test]
+    fn multiple() {
+        assert_eq!(parse("bytes=0-9, 20-29", 100), Ok(vec![r(0, 9), r(20, 29)]));
+        assert_eq!(parse("bytes=20-29,0-9", 100), Ok(vec![r(0, 9), r(20, 29)]));
+        assert_eq!(parse("bytes=0-9,5-15", 100), Ok(vec![r(0, 15)]));
+        assert_eq!(parse("bytes=0-9,10-19", 100), Ok(vec![r(0, 19)]));
+        assert_eq!(parse("bytes=0-9,200-300", 100), Ok(vec![r(0, 9)]));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse("bytes=100-", 100), Err(RangeError::Unsatisfiable));
+        assert_eq!(parse("bytes=-0", 100), Err(RangeError::Unsatisfiable));
+        assert_eq!(parse("bytes=0-", 0), Err(RangeError::Unsatisfiable));
+        assert_eq!(parse("bytes=9-0", 100), Err(RangeError::Invalid));
+        assert_eq!(parse("bytes=-", 100), Err(RangeError::Invalid));
+        assert_eq!(parse("bytes=+1-2", 100), Err(RangeError::Invalid));
+        assert_eq!(parse("bytes=a-b", 100), Err(RangeError::Invalid));
+        assert_eq!(parse("items=0-1", 100), Err(RangeError::Invalid));
+        assert_eq!(parse("bytes 0-1", 100), Err(RangeError::Invalid));
+    }
+}
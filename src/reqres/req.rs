@@ -1,7 +1,11 @@
 use std::fmt;
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+
+use percent_encoding_lite::Bitmask;
 
 use crate::reqres::HttpHeader;
+use crate::util::qvalue;
 
 /// Version used in request
 #[derive(Clone, Copy)]
@@ -11,18 +15,30 @@ pub struct HttpVersion {
 }
 
 impl HttpVersion {
+    /// `HTTP/1.0`
+    pub const HTTP_1_0: HttpVersion = HttpVersion { major: 1, minor: 0 };
+    /// `HTTP/1.1`
+    pub const HTTP_1_1: HttpVersion = HttpVersion { major: 1, minor: 1 };
+
     /// Compares this version for equality
     pub fn is(self, major: u8, minor: u8) -> bool {
         self.major == major && self.minor == minor
     }
 }
 
-impl fmt::Debug for HttpVersion {
+/// Wire format, like `HTTP/1.1`
+impl fmt::Display for HttpVersion {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(fmt, "HTTP/{}.{}", self.major, self.minor)
     }
 }
 
+impl fmt::Debug for HttpVersion {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, fmt)
+    }
+}
+
 /// Method of request
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum HttpMethod {
@@ -74,6 +90,9 @@ impl fmt::Display for HttpMethod {
 }
 
 /// Request from client to handle
+///
+/// Body is framed with `Content-Length` or `Transfer-Encoding: chunked`, other transfer codings are rejected with
+/// `501 Not implemented`. Out of `TE`, only `trailers` is recognized (see [`HttpRequest::accepts_trailers`])
 #[derive(Debug, Clone)]
 #[non_exhaustive]
 pub struct HttpRequest {
@@ -83,8 +102,32 @@ pub struct HttpRequest {
     pub headers: Vec<HttpHeader>,
     /// Contents of the `Content-Length` header
     pub len: u64,
+    /// Body is sent with chunked encoding, its length is not known in advance (`len` is 0)
+    ///
+    /// It is decoded by the server, services read it as usual
+    pub chunked: bool,
     /// IP address of this request (`0.0.0.0` if none)
     pub addr: IpAddr,
+    /// Local address of the connection, tells which listener it came from (`0.0.0.0:0` if none)
+    pub local_addr: SocketAddr,
+    /// Connection is encrypted with TLS
+    pub secure: bool,
+    /// Request came from one of [`HttpServer::trusted_proxies`], so its `Forwarded` headers can be believed
+    ///
+    /// [`HttpServer::trusted_proxies`]: crate::server::HttpServer::trusted_proxies
+    pub trusted_proxy: bool,
+    /// Certificate chain of the client (DER, its own certificate first), if it has sent one over TLS
+    ///
+    /// Clients only send it when the TLS config asks for it (mutual TLS), see [`serve_tls`](crate::serve_tls).
+    /// Parse it with an X.509 crate to read the subject or SANs
+    pub peer_certificates: Option<Arc<Vec<Vec<u8>>>>,
+    /// Request line and headers exactly as received, including the empty line at the end
+    ///
+    /// Parsed [`headers`](HttpRequest::headers) keep the original case of names, but their values are trimmed.
+    /// Folded headers (obsolete line continuations) are rejected
+    pub raw: String,
+    /// Filled by the chunked body decoder, see [`HttpRequest::trailers`]
+    pub(crate) trailers: Arc<OnceLock<Vec<HttpHeader>>>,
 }
 
 impl HttpRequest {
@@ -100,6 +143,14 @@ impl HttpRequest {
         header
     }
 
+    /// Retrieves values of a repeated header, in the order they were received
+    ///
+    /// Headers are parsed once into [`headers`](HttpRequest::headers), so this is a single pass over them
+    /// (which are bounded by [`HttpServer::max_headers_size`](crate::server::HttpServer::max_headers_size))
+    pub fn get_all_headers<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.headers.iter().filter(move |h| h.name.eq_ignore_ascii_case(name)).map(|h| h.value.as_str())
+    }
+
     /// Checks if this header exists
     pub fn has_header(&self, name: &str) -> bool {
         self.get_header(name).is_some()
@@ -110,6 +161,156 @@ impl HttpRequest {
         let hdr = self.get_header(name);
         hdr.is_some() && hdr.unwrap().eq_ignore_ascii_case(value)
     }
+
+    /// Scheme the client has used, `"https"` or `"http"`
+    ///
+    /// It is taken from the connection, unless the request came from a [trusted proxy](HttpRequest::trusted_proxy).
+    /// Then `proto` of the first `Forwarded` element wins, then the first `X-Forwarded-Proto`
+    /// ```
+    /// # use dhttp::reqres::HttpRequest;
+    /// assert_eq!(HttpRequest::default().scheme(), "http");
+    /// ```
+    pub fn scheme(&self) -> &'static str {
+        match self.forwarded("proto", "X-Forwarded-Proto") {
+            Some(proto) if proto.eq_ignore_ascii_case("https") => "https",
+            Some(proto) if proto.eq_ignore_ascii_case("http") => "http",
+            _ if self.secure => "https",
+            _ => "http",
+        }
+    }
+
+    /// Host the request was sent to, from `Host`, or from `Forwarded` and `X-Forwarded-Host` like in [`scheme`](HttpRequest::scheme)
+    ///
+    /// `None` if it is missing (HTTP/1.0 clients), or has anything besides a name or an IP and a port
+    pub fn host(&self) -> Option<&str> {
+        let host = self.forwarded("host", "X-Forwarded-Host").or_else(|| self.get_header("Host"))?;
+        let valid = !host.is_empty() && host.bytes().all(|b| b.is_ascii_alphanumeric() || b"-._~[]:".contains(&b));
+        valid.then_some(host)
+    }
+
+    /// Builds `scheme://host/path` out of [`scheme`](HttpRequest::scheme) and [`host`](HttpRequest::host)
+    ///
+    /// `path` is percent-encoded like in [`path::encode`](crate::util::path::encode), so append the query after.
+    /// `None` if there is no valid host
+    /// ```
+    /// # use dhttp::reqres::{HttpRequest, HttpHeader};
+    /// let mut req = HttpRequest::default();
+    /// req.headers.push(HttpHeader { name: "Host".to_string(), value: "example.com".to_string() });
+    /// assert_eq!(req.absolute_url("/a b").unwrap(), "http://example.com/a%20b");
+    /// ```
+    pub fn absolute_url(&self, path: &str) -> Option<String> {
+        let host = self.host()?;
+        let path = path.strip_prefix('/').unwrap_or(path);
+        Some(format!("{}://{host}/{}", self.scheme(), percent_encoding_lite::encode(path, Bitmask::PATH)))
+    }
+
+    /// Parameter of the first `Forwarded` element, or else the first value of the `X-Forwarded-*` header,
+    /// only if the request came from a trusted proxy
+    fn forwarded(&self, param: &str, header: &str) -> Option<&str> {
+        if !self.trusted_proxy { return None; }
+        let element = self.get_header("Forwarded").and_then(|value| value.split(',').next()).unwrap_or_default();
+        let value = element.split(';').find_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            name.trim().eq_ignore_ascii_case(param).then(|| value.trim().trim_matches('"'))
+        });
+        let value = value.or_else(|| self.get_header(header)?.split(',').next())?;
+        Some(value.trim())
+    }
+
+    /// Checks if client has asked to upgrade the connection to this protocol
+    /// (`Connection: Upgrade` and `Upgrade: <protocol>`)
+    ///
+    /// Upgrades are optional, services which don't support them just respond as usual
+    pub fn wants_upgrade(&self, protocol: &str) -> bool {
+        let connection = self.get_header("Connection").unwrap_or_default();
+        let upgrade = self.get_header("Upgrade").unwrap_or_default();
+        connection.split(',').any(|option| option.trim().eq_ignore_ascii_case("upgrade"))
+            && upgrade.split(',').any(|proto| proto.trim().eq_ignore_ascii_case(protocol))
+    }
+
+    /// Trailer fields sent after a chunked body, like a `Content-Digest` of the upload
+    ///
+    /// They only arrive after the body, so this is `None` until the service has read all of it.
+    /// Capture is opt-in with [`HttpServer::capture_trailers`], otherwise trailers are dropped and it stays `None`
+    ///
+    /// [`HttpServer::capture_trailers`]: crate::server::HttpServer::capture_trailers
+    pub fn trailers(&self) -> Option<&[HttpHeader]> {
+        self.trailers.get().map(Vec::as_slice)
+    }
+
+    /// Checks if client is willing to accept trailer fields (`TE: trailers`)
+    pub fn accepts_trailers(&self) -> bool {
+        let te = self.get_header("TE").unwrap_or_default();
+        te.split(',').any(|coding| coding.trim().eq_ignore_ascii_case("trailers"))
+    }
+
+    /// Picks the best language out of `available` according to `Accept-Language`
+    ///
+    /// Ranges like `en` also match `en-US`, `*` matches anything, and `q=0` excludes a language.
+    /// Ties are resolved in the order of `available`. Without the header, first language is returned
+    pub fn preferred_language<'a>(&self, available: &[&'a str]) -> Option<&'a str> {
+        let Some(header) = self.get_header("Accept-Language") else {
+            return available.first().copied();
+        };
+
+        let mut best = None;
+        let mut best_q = 0.0;
+        for &lang in available {
+            // Most specific range decides the weight
+            let mut matched = None;
+            for (range, q) in qvalue::parse(header) {
+                let matches = range == "*" || lang.eq_ignore_ascii_case(range)
+                    || lang.get(..range.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(range))
+                        && lang.as_bytes().get(range.len()) == Some(&b'-');
+                // `*` is the least specific, so give it length 0
+                let len = if range == "*" { 0 } else { range.len() };
+                if matches && matched.is_none_or(|(l, _)| len > l) {
+                    matched = Some((len, q));
+                }
+            }
+            if let Some((_, q)) = matched && q > best_q {
+                best = Some(lang);
+                best_q = q;
+            }
+        }
+        best
+    }
+
+    /// Picks the best media type out of `available` according to `Accept`
+    ///
+    /// Works like [`preferred_language`](HttpRequest::preferred_language), with `text/*` and `*/*` wildcards
+    pub fn preferred_type<'a>(&self, available: &[&'a str]) -> Option<&'a str> {
+        let Some(header) = self.get_header("Accept") else {
+            return available.first().copied();
+        };
+
+        let mut best = None;
+        let mut best_q = 0.0;
+        for &ty in available {
+            let mut matched = None;
+            for (range, q) in qvalue::parse(header) {
+                let specificity = if range == "*/*" {
+                    0
+                } else if let Some(main) = range.strip_suffix("/*") {
+                    let same = ty.split_once('/').is_some_and(|(ty_main, _)| ty_main.eq_ignore_ascii_case(main));
+                    if !same { continue; }
+                    1
+                } else if ty.eq_ignore_ascii_case(range) {
+                    2
+                } else {
+                    continue;
+                };
+                if matched.is_none_or(|(s, _)| specificity > s) {
+                    matched = Some((specificity, q));
+                }
+            }
+            if let Some((_, q)) = matched && q > best_q {
+                best = Some(ty);
+                best_q = q;
+            }
+        }
+        best
+    }
 }
 
 impl Default for HttpRequest {
@@ -120,7 +321,100 @@ impl Default for HttpRequest {
             version: HttpVersion { major: 0, minor: 0 },
             headers: vec![],
             len: 0,
+            chunked: false,
             addr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            local_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            secure: false,
+            trusted_proxy: false,
+            peer_certificates: None,
+            raw: String::new(),
+            trailers: Arc::default(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reqres::header;
+
+    fn req(accept_language: &str) -> HttpRequest {
+        HttpRequest { headers: vec![header("Accept-Language", accept_language)], ..Default::default() }
+    }
+
+    #[test]
+    fn preferred_type() {
+        let accept = |value: &str| {
+            HttpRequest { headers: vec![header("Accept", value)], ..Default::default() }
+        };
+        let available = ["text/html", "application/json"];
+        assert_eq!(HttpRequest::default().preferred_type(&available), Some("text/html"));
+        assert_eq!(accept("application/json").preferred_type(&available), Some("application/json"));
+        assert_eq!(accept("text/html,application/xhtml+xml,*/*;q=0.8").preferred_type(&available), Some("text/html"));
+        assert_eq!(accept("application/*").preferred_type(&available), Some("application/json"));
+        assert_eq!(accept("*/*, text/*;q=0").preferred_type(&available), Some("application/json"));
+        assert_eq!(accept("image/png").preferred_type(&available), None);
+    }
+
+    #[test]
+    fn get_all_headers() {
+        let req = HttpRequest {
+            headers: vec![header("Cookie", "a=1"), header("Host", "x"), header("cookie", "b=2")],
+            ..Default::default()
+        };
+        assert_eq!(req.get_all_headers("COOKIE").collect::<Vec<_>>(), ["a=1", "b=2"]);
+        assert_eq!(req.get_all_headers("Accept").count(), 0);
+    }
+
+    #[test]
+    fn scheme() {
+        let mut req = HttpRequest {
+            headers: vec![header("Forwarded", "for=1.2.3.4;Proto=\"https\", proto=http"), header("X-Forwarded-Proto", "http")],
+            ..Default::default()
+        };
+        assert_eq!(req.scheme(), "http");
+        req.trusted_proxy = true;
+        assert_eq!(req.scheme(), "https");
+        req.headers = vec![header("X-Forwarded-Proto", "https, http")];
+        assert_eq!(req.scheme(), "https");
+        req.headers = vec![header("X-Forwarded-Proto", "gopher")];
+        req.secure = true;
+        assert_eq!(req.scheme(), "https");
+    }
+
+    #[test]
+    fn absolute_url() {
+        let mut req = HttpRequest {
+            headers: vec![header("Host", "example.com:8080"), header("X-Forwarded-Host", "proxied.example.com")],
+            ..Default::default()
+        };
+        assert_eq!(req.absolute_url("/a/b c").unwrap(), "http://example.com:8080/a/b%20c");
+        req.trusted_proxy = true;
+        assert_eq!(req.host(), Some("proxied.example.com"));
+        req.headers = vec![header("Host", "evil.com/path")];
+        assert_eq!(req.absolute_url("/"), None);
+        req.headers = vec![];
+        assert_eq!(req.absolute_url("/"), None);
+    }
+
+    #[test]
+    fn version_wire_format() {
+        assert_eq!(HttpVersion::HTTP_1_0.to_string(), "HTTP/1.0");
+        assert_eq!(HttpVersion { major: 2, minor: 0 }.to_string(), "HTTP/2.0");
+    }
+
+    #[test]
+    fn preferred_language() {
+        let available = ["en-US", "de", "fr"];
+        assert_eq!(HttpRequest::default().preferred_language(&available), Some("en-US"));
+        assert_eq!(req("de").preferred_language(&available), Some("de"));
+        assert_eq!(req("fr;q=0.5, de;q=0.8").preferred_language(&available), Some("de"));
+        assert_eq!(req("en").preferred_language(&available), Some("en-US"));
+        assert_eq!(req("e").preferred_language(&available), None);
+        assert_eq!(req("*").preferred_language(&available), Some("en-US"));
+        assert_eq!(req("*, en;q=0").preferred_language(&available), Some("de"));
+        assert_eq!(req("fr, de").preferred_language(&available), Some("de"));
+        assert_eq!(req("ja").preferred_language(&available), None);
+        assert_eq!(req("DE-at, de;q=0.9").preferred_language(&available), Some("de"));
+    }
+}
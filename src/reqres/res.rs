@@ -1,18 +1,27 @@
 //! HTTP response and its constructors
 
+use std::fmt;
+use std::pin::Pin;
+
 use blake3_lite::Hasher;
+use tokio::io::AsyncRead;
 
 use crate::reqres::{HttpRequest, HttpHeader, HttpBody, StatusCode};
 use crate::reqres::sse::HttpSse;
+use crate::reqres::upgrade::HttpUpgrade;
+use crate::util::escape;
 
 /// Your response
-#[derive(Debug)]
 #[non_exhaustive]
 pub struct HttpResponse {
     pub code: StatusCode,
     pub headers: Vec<HttpHeader>,
     pub body: HttpBody,
     pub content_type: String,
+    /// Interim `1xx` responses sent before this one, see [`HttpResponse::early_hint`]
+    pub informational: Vec<(StatusCode, Vec<HttpHeader>)>,
+    /// Tasks spawned after this response is sent, see [`HttpResponse::after_response`]
+    pub background: Vec<Pin<Box<dyn Future<Output = ()> + Send>>>,
 }
 
 impl HttpResponse {
@@ -27,6 +36,81 @@ impl HttpResponse {
         self
     }
 
+    /// Retrieves a header value, if any
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|h| h.name.eq_ignore_ascii_case(name)).map(|h| h.value.as_str())
+    }
+
+    /// Retrieves all values of a repeated header, like `Set-Cookie`, in the order they were added
+    /// ```
+    /// # use dhttp::reqres::res;
+    /// let mut res = res::text("hi");
+    /// res.add_header("Set-Cookie", "a=1").add_header("Set-Cookie", "b=2");
+    /// assert_eq!(res.get_all_headers("set-cookie").collect::<Vec<_>>(), ["a=1", "b=2"]);
+    /// ```
+    pub fn get_all_headers<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.headers.iter().filter(move |h| h.name.eq_ignore_ascii_case(name)).map(|h| h.value.as_str())
+    }
+
+    /// Checks if this header was added
+    pub fn has_header(&self, name: &str) -> bool {
+        self.get_header(name).is_some()
+    }
+
+    /// Adds an interim `1xx` response, it is sent before this one (HTTP/1.1 clients only)
+    ///
+    /// `101 Switching protocols` is not interim and is ignored, use [`upgrade`] instead
+    pub fn add_informational(&mut self, code: StatusCode, headers: Vec<HttpHeader>) -> &mut HttpResponse {
+        self.informational.push((code, headers));
+        self
+    }
+
+    /// Adds a `Link` header to `103 Early hints`, so the client can start preloading it
+    /// ```
+    /// # use dhttp::reqres::res;
+    /// let mut res = res::text("page");
+    /// res.early_hint("</style.css>; rel=preload; as=style");
+    /// ```
+    pub fn early_hint(&mut self, link: impl Into<String>) -> &mut HttpResponse {
+        let header = HttpHeader { name: "Link".to_string(), value: link.into() };
+        match self.informational.last_mut() {
+            Some((code, headers)) if code.0 == StatusCode::EARLY_HINTS.0 => headers.push(header),
+            _ => self.informational.push((StatusCode::EARLY_HINTS, vec![header])),
+        }
+        self
+    }
+
+    /// Runs `task` after this response was sent to the client, so it doesn't delay it.
+    /// Useful for webhooks or audit logs
+    ///
+    /// Task is spawned on the server's runtime and runs detached: nothing waits for it, and
+    /// its panic doesn't affect the connection. If the response could not be sent, it never runs
+    /// ```
+    /// # use dhttp::reqres::res;
+    /// let mut res = res::text("saved");
+    /// res.after_response(async {
+    ///     // notify other services
+    /// });
+    /// ```
+    pub fn after_response(&mut self, task: impl Future<Output = ()> + Send + 'static) -> &mut HttpResponse {
+        self.background.push(Box::pin(task));
+        self
+    }
+
+    /// Asks the server to close the connection after this response, even if it could be kept alive
+    pub fn close_connection(&mut self) -> &mut HttpResponse {
+        if !self.is_closing() {
+            self.headers.retain(|h| !h.name.eq_ignore_ascii_case("Connection"));
+            self.add_header("Connection", "close");
+        }
+        self
+    }
+
+    /// Checks if [`close_connection`](HttpResponse::close_connection) was requested
+    pub fn is_closing(&self) -> bool {
+        self.get_header("Connection").is_some_and(|v| v.eq_ignore_ascii_case("close"))
+    }
+
     /// Constructs new response with a specified `Content-Type`
     pub fn with_type(content_type: impl Into<String>, body: impl Into<HttpBody>) -> HttpResponse {
         HttpResponse {
@@ -34,10 +118,23 @@ impl HttpResponse {
             headers: vec![],
             body: body.into(),
             content_type: content_type.into(),
+            informational: vec![],
+            background: vec![],
         }
     }
 }
 
+impl fmt::Debug for HttpResponse {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("HttpResponse")
+            .field("status", &format_args!("{} {}", self.code, self.code.as_str()))
+            .field("headers", &self.headers)
+            .field("content_type", &self.content_type)
+            .field("body", &self.body)
+            .finish()
+    }
+}
+
 impl Default for HttpResponse {
     fn default() -> HttpResponse {
         HttpResponse::new()
@@ -50,45 +147,166 @@ pub fn bytes(bytes: Vec<u8>) -> HttpResponse {
 }
 
 /// Plaintext response (`text/plain`)
+///
+/// Text is sent verbatim, so add a trailing newline yourself if needed
 pub fn text(text: impl Into<String>) -> HttpResponse {
     HttpResponse::with_type("text/plain; charset=utf-8", text.into())
 }
 
+/// Plaintext response (`text/plain`) in a specified charset
+///
+/// Body is sent verbatim, it has to be already encoded in this charset
+pub fn text_with_charset(charset: &str, text: impl Into<HttpBody>) -> HttpResponse {
+    HttpResponse::with_type(format!("text/plain; charset={charset}"), text)
+}
+
 /// HTML response (`text/html`)
 ///
-/// Also stamps an ETag to enable caching
+/// Also stamps an ETag to enable caching. It is computed for HEAD requests too,
+/// because they have to get the same headers as GET
 pub fn html(req: &HttpRequest, html: impl Into<String>) -> HttpResponse {
-    let html = html.into();
+    cached(req, "text/html; charset=utf-8", html.into().into_bytes())
+}
 
+/// JSON response (`application/json`)
+pub fn json(json: impl Into<String>) -> HttpResponse {
+    HttpResponse::with_type("application/json", json.into())
+}
+
+/// JSON response (`application/json`) with an ETag, like [`html`]
+///
+/// Body has to be hashed, so use it only for responses which are worth caching
+pub fn json_cached(req: &HttpRequest, json: impl Into<String>) -> HttpResponse {
+    cached(req, "application/json", json.into().into_bytes())
+}
+
+/// Plaintext response (`text/plain`) with an ETag, like [`html`]
+pub fn text_cached(req: &HttpRequest, text: impl Into<String>) -> HttpResponse {
+    cached(req, "text/plain; charset=utf-8", text.into().into_bytes())
+}
+
+/// HTML or JSON response, whichever is preferred by the client (HTML if it doesn't care)
+///
+/// Only the chosen representation is rendered
+/// ```
+/// # use dhttp::reqres::{res, HttpRequest, StatusCode};
+/// # let req = HttpRequest::default();
+/// let res = res::negotiated(&req, StatusCode::OK, || "<p>hello</p>".to_string(), || r#"{"text":"hello"}"#.to_string());
+/// assert_eq!(res.content_type, "text/html; charset=utf-8");
+/// ```
+pub fn negotiated(req: &HttpRequest, code: StatusCode, html: impl FnOnce() -> String, json: impl FnOnce() -> String) -> HttpResponse {
+    let mut res = match req.preferred_type(&["text/html", "application/json"]) {
+        Some("application/json") => HttpResponse::with_type("application/json", json()),
+        _ => HttpResponse::with_type("text/html; charset=utf-8", html()),
+    };
+    res.code = code;
+    res.add_header("Vary", "Accept");
+    res
+}
+
+/// Stamps an ETag from the body hash, responding with `304 Not modified` if client has it already
+fn cached(req: &HttpRequest, content_type: &str, body: Vec<u8>) -> HttpResponse {
+    let etag = etag(&body);
+    with_etag(req, content_type, body, etag)
+}
+
+/// Strong ETag from the body hash
+pub(super) fn etag(body: &[u8]) -> String {
     let mut hasher = Hasher::new();
-    hasher.update(html.as_bytes());
+    hasher.update(body);
     // Default length is 32, we are truncating it
     let mut hash = [0; 8];
     hasher.finalize(&mut hash);
-    let hex = format!("\"{}\"", crate::util::hex(&hash));
+    format!("\"{}\"", crate::util::hex(&hash))
+}
 
-    let mut res = HttpResponse::with_type("text/html; charset=utf-8", html);
-    if req.cmp_header("If-None-Match", &hex) {
+/// HTML response (`text/html`) with your own ETag instead of the body hash
+///
+/// Use it when the page changes on every render (timestamps, nonces), but you know its real version.
+/// `W/` prefix makes it weak, quotes are added if missing
+/// ```
+/// # use dhttp::reqres::{res, HttpRequest};
+/// # let req = HttpRequest::default();
+/// let res = res::html_with_etag(&req, "W/\"v42\"", "<p>Rendered at 12:00</p>");
+/// assert_eq!(res.get_header("ETag"), Some("W/\"v42\""));
+/// ```
+pub fn html_with_etag(req: &HttpRequest, etag: &str, html: impl Into<String>) -> HttpResponse {
+    let (weak, tag) = match etag.strip_prefix("W/") {
+        Some(tag) => ("W/", tag),
+        None => ("", etag),
+    };
+    let etag = if tag.starts_with('"') && tag.ends_with('"') && tag.len() >= 2 {
+        format!("{weak}{tag}")
+    } else {
+        format!("{weak}\"{tag}\"")
+    };
+    with_etag(req, "text/html; charset=utf-8", html.into().into_bytes(), etag)
+}
+
+/// Responds with `304 Not modified` if `If-None-Match` has this ETag
+fn with_etag(req: &HttpRequest, content_type: &str, body: Vec<u8>, etag: String) -> HttpResponse {
+    let mut res = HttpResponse::with_type(content_type, body);
+    if req.get_header("If-None-Match").is_some_and(|header| etag_matches(header, &etag)) {
         res.code = StatusCode::NOT_MODIFIED;
         res.body = HttpBody::Empty;
     }
-    res.add_header("ETag", hex);
+    res.add_header("ETag", etag);
     res
 }
 
-/// JSON response (`application/json`)
-pub fn json(json: impl Into<String>) -> HttpResponse {
-    HttpResponse::with_type("application/json", json.into())
+/// `If-None-Match` uses weak comparison: `W/"a"` matches `"a"`
+pub(crate) fn etag_matches(header: &str, etag: &str) -> bool {
+    fn opaque(tag: &str) -> &str {
+        let tag = tag.trim();
+        tag.strip_prefix("W/").unwrap_or(tag)
+    }
+    header.trim() == "*" || header.split(',').any(|tag| opaque(tag) == opaque(etag))
 }
 
-/// HTTP redirect with the `Location` header
+/// Permanent redirect with the `Location` header (`301 Moved permanently`)
+///
+/// Clients may change `POST` into `GET` when following it. Which redirect to use:
+///
+/// | | Method may change | Method is kept |
+/// |-|-|-|
+/// | Permanent | [`redirect`] (301) | [`permanent_redirect`] (308) |
+/// | Temporary | [`found`] (302) | [`temporary_redirect`] (307) |
+///
+/// And [`see_other`] (303) always turns the request into `GET`, use it after handling a form
 pub fn redirect(dest: impl Into<String>) -> HttpResponse {
-    let dest = dest.into();
+    redirect_with(StatusCode::MOVED_PERMANENTLY, dest.into())
+}
+
+/// Temporary redirect (`302 Found`), clients may change `POST` into `GET`
+pub fn found(dest: impl Into<String>) -> HttpResponse {
+    redirect_with(StatusCode::FOUND, dest.into())
+}
+
+/// Redirect to a page that has to be fetched with `GET` (`303 See other`)
+pub fn see_other(dest: impl Into<String>) -> HttpResponse {
+    redirect_with(StatusCode::SEE_OTHER, dest.into())
+}
+
+/// Temporary redirect that keeps the method and body (`307 Temporary redirect`)
+pub fn temporary_redirect(dest: impl Into<String>) -> HttpResponse {
+    redirect_with(StatusCode::TEMPORARY_REDIRECT, dest.into())
+}
+
+/// Permanent redirect that keeps the method and body (`308 Permanent redirect`)
+pub fn permanent_redirect(dest: impl Into<String>) -> HttpResponse {
+    redirect_with(StatusCode::PERMANENT_REDIRECT, dest.into())
+}
+
+fn redirect_with(code: StatusCode, dest: String) -> HttpResponse {
+    // Spaces and non-ASCII are not allowed in the header
+    let dest = escape::uri(&dest);
     HttpResponse {
-        code: StatusCode::MOVED_PERMANENTLY,
-        body: format!("<a href=\"{}\">Click here if you weren't redirected</a>\n", &dest).into(),
+        code,
+        body: format!("<a href=\"{}\">Click here if you weren't redirected</a>\n", escape::html(&dest)).into(),
         headers: vec![HttpHeader { name: "Location".to_string(), value: dest }],
         content_type: "text/html; charset=utf-8".to_string(),
+        informational: vec![],
+        background: vec![],
     }
 }
 
@@ -96,4 +314,49 @@ pub fn sse(handler: impl HttpSse) -> HttpResponse {
     HttpResponse::with_type("text/event-stream", HttpBody::Sse(Box::new(handler)))
 }
 
-pub use super::file::file;
+/// Switches the connection to another protocol (`101 Switching Protocols`)
+///
+/// Check that client supports it first with [`HttpRequest::wants_upgrade`]
+pub fn upgrade(protocol: impl Into<String>, handler: impl HttpUpgrade) -> HttpResponse {
+    let mut res = HttpResponse::with_type("", HttpBody::Upgrade(Box::new(handler)));
+    res.code = StatusCode::SWITCHING_PROTOCOLS;
+    res.add_header("Upgrade", protocol);
+    res.add_header("Connection", "Upgrade");
+    res
+}
+
+/// Response streamed from a reader, for bodies of unknown length
+///
+/// Everything read from the reader is sent with chunked encoding until EOF, so memory use stays bounded.
+/// Check `examples/stream.rs` for writing the body from another task
+pub fn stream(content_type: impl Into<String>, reader: impl AsyncRead + Send + Unpin + 'static) -> HttpResponse {
+    HttpResponse::with_type(content_type, HttpBody::Stream(Box::new(reader)))
+}
+
+/// Like [`stream`], but `HEAD` requests get this `Content-Length`, since they have no body to measure
+///
+/// `GET` is still sent with chunked encoding. The length has to match what the reader delivers,
+/// download managers send `HEAD` first and may rely on it
+pub fn stream_with_len(content_type: impl Into<String>, len: u64, reader: impl AsyncRead + Send + Unpin + 'static) -> HttpResponse {
+    let mut res = stream(content_type, reader);
+    res.add_header("Content-Length", len.to_string());
+    res
+}
+
+pub use super::file::{file, seekable, cacheable};
+
+#[cfg(test)]
+mod tests {
+    use super::etag_matches;
+
+    #[test]
+    fn etag_comparison() {
+        assert!(etag_matches("\"a\"", "\"a\""));
+        assert!(etag_matches("W/\"a\"", "\"a\""));
+        assert!(etag_matches("\"a\"", "W/\"a\""));
+        assert!(etag_matches("\"b\", W/\"a\"", "\"a\""));
+        assert!(etag_matches("*", "\"a\""));
+        assert!(!etag_matches("\"b\"", "\"a\""));
+        assert!(!etag_matches("\"ab\"", "\"a\""));
+    }
+}
@@ -22,14 +22,28 @@
 //! ```
 
 use std::fmt::Write;
+use std::future::poll_fn;
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::task::{Poll, Waker};
 
+use crate::util::future::Or;
+
+/// Single event, serialized
 pub struct HttpSseEvent(pub(crate) String);
 
+/// Events bigger than this abort the stream, so a single event can't grow without bound
+pub const MAX_EVENT_SIZE: usize = 1 << 20; // 1MB
+
+// Each line gets its own `data:` field, and client joins them back with `\n`.
+// Trailing newline becomes an empty field, so it survives the round trip
 fn add_data(event: &mut String, data: &str) {
-    for line in data.split('\n') {
+    // `\r\n`, `\r` and `\n` are all line endings in SSE
+    for line in data.split("\r\n").flat_map(|line| line.split(['\r', '\n'])) {
         writeln!(event, "data: {}", line).unwrap();
     }
+    // blank line ends the event
     event.push('\n');
 }
 
@@ -41,10 +55,15 @@ impl HttpSseEvent {
     }
 
     pub fn named(name: &str, data: &str) -> HttpSseEvent {
-        let mut event = format!("event: {}\n", name.replace('\n', ""));
+        let mut event = format!("event: {}\n", name.replace(['\r', '\n'], ""));
         add_data(&mut event, data);
         HttpSseEvent(event)
     }
+
+    /// Event with binary data, encoded in base64 (client has to decode it with `atob`)
+    pub fn binary(data: &[u8]) -> HttpSseEvent {
+        HttpSseEvent::new(&crate::util::base64(data))
+    }
 }
 
 /// SSE stream
@@ -71,3 +90,161 @@ impl<T: HttpSse> HttpSseRaw for T {
         Box::pin(self.next())
     }
 }
+
+/// Ends SSE streams from outside, e.g. when the user has logged out
+///
+/// Clones control the same streams. An event which is already being sent is finished first,
+/// so the client never gets half of it
+/// ```
+/// # use dhttp::reqres::res;
+/// # use dhttp::reqres::sse::{HttpSse, HttpSseEvent, SseAbort};
+/// # struct Updates;
+/// # impl HttpSse for Updates {
+/// #     async fn next(&mut self) -> Option<HttpSseEvent> { None }
+/// # }
+/// let abort = SseAbort::new();
+/// let res = res::sse(abort.wrap(Updates));
+/// // later, from anywhere
+/// abort.abort();
+/// ```
+#[derive(Clone, Default)]
+pub struct SseAbort {
+    state: Arc<AbortState>,
+}
+
+#[derive(Default)]
+struct AbortState {
+    aborted: AtomicBool,
+    /// Id for the next wrapped stream
+    next_id: AtomicU64,
+    /// Wakers of the streams waiting for an event, by stream id
+    wakers: Mutex<Vec<(u64, Waker)>>,
+}
+
+/// Removes the waker once its stream stops waiting, so finished streams don't pile up
+struct Unregister<'a> {
+    state: &'a AbortState,
+    id: u64,
+}
+
+impl Drop for Unregister<'_> {
+    fn drop(&mut self) {
+        self.state.wakers.lock().unwrap().retain(|(id, _)| *id != self.id);
+    }
+}
+
+impl SseAbort {
+    pub fn new() -> SseAbort {
+        SseAbort::default()
+    }
+
+    /// Ends all streams wrapped with this handle (and ones wrapped later)
+    pub fn abort(&self) {
+        self.state.aborted.store(true, Ordering::Release);
+        for (_, waker) in self.state.wakers.lock().unwrap().drain(..) {
+            waker.wake();
+        }
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.state.aborted.load(Ordering::Acquire)
+    }
+
+    /// Wraps a stream, so it ends on [`abort`](SseAbort::abort)
+    pub fn wrap<S: HttpSse>(&self, events: S) -> Abortable<S> {
+        let id = self.state.next_id.fetch_add(1, Ordering::Relaxed);
+        Abortable { events, abort: self.clone(), id }
+    }
+
+    /// Completes once aborted, `id` is the waiting stream
+    async fn aborted(&self, id: u64) {
+        let _unregister = Unregister { state: &self.state, id };
+        poll_fn(|cx| {
+            if self.is_aborted() { return Poll::Ready(()); }
+            let mut wakers = self.state.wakers.lock().unwrap();
+            match wakers.iter_mut().find(|(waiting, _)| *waiting == id) {
+                Some((_, waker)) => if !waker.will_wake(cx.waker()) { waker.clone_from(cx.waker()) },
+                None => wakers.push((id, cx.waker().clone())),
+            }
+            drop(wakers);
+            // It may have been aborted before the waker was added
+            if self.is_aborted() { Poll::Ready(()) } else { Poll::Pending }
+        }).await
+    }
+}
+
+/// Stream that ends on [`SseAbort::abort`], created by [`SseAbort::wrap`]
+pub struct Abortable<S> {
+    events: S,
+    abort: SseAbort,
+    id: u64,
+}
+
+impl<S: HttpSse> HttpSse for Abortable<S> {
+    async fn next(&mut self) -> Option<HttpSseEvent> {
+        if self.abort.is_aborted() { return None; }
+        Or::new(self.events.next(), self.abort.aborted(self.id)).await.ok().flatten()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HttpSse, HttpSseEvent, SseAbort};
+
+    fn data(data: &str) -> String {
+        HttpSseEvent::new(data).0
+    }
+
+    #[test]
+    fn serialize() {
+        assert_eq!(data("hello"), "data: hello\n\n");
+        assert_eq!(data(""), "data: \n\n");
+        assert_eq!(data("a\nb"), "data: a\ndata: b\n\n");
+        assert_eq!(data("a\n"), "data: a\ndata: \n\n");
+        assert_eq!(data("a\r\nb\rc"), "data: a\ndata: b\ndata: c\n\n");
+        assert_eq!(HttpSseEvent::named("up\r\ndate", "1").0, "event: update\ndata: 1\n\n");
+        assert_eq!(HttpSseEvent::binary(b"\n\0").0, "data: CgA=\n\n");
+    }
+
+    /// Never produces anything
+    struct Silent;
+    impl HttpSse for Silent {
+        async fn next(&mut self) -> Option<HttpSseEvent> {
+            std::future::pending().await
+        }
+    }
+
+    #[test]
+    fn abort() {
+        let abort = SseAbort::new();
+        let mut events = abort.wrap(Silent);
+        let rt = crate::server::tokio_rt().unwrap();
+        let abort2 = abort.clone();
+        rt.spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            abort2.abort();
+        });
+        assert!(rt.block_on(events.next()).is_none());
+        assert!(rt.block_on(events.next()).is_none());
+    }
+
+    /// Sends an event after a while
+    struct Slow;
+    impl HttpSse for Slow {
+        async fn next(&mut self) -> Option<HttpSseEvent> {
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+            Some(HttpSseEvent::new("tick"))
+        }
+    }
+
+    #[test]
+    fn abort_forgets_wakers() {
+        let abort = SseAbort::new();
+        let mut events = abort.wrap(Slow);
+        let rt = crate::server::tokio_rt().unwrap();
+        for _ in 0..3 {
+            assert!(rt.block_on(events.next()).is_some());
+        }
+        assert!(abort.state.wakers.lock().unwrap().is_empty());
+    }
+}
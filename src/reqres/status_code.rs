@@ -8,38 +8,83 @@ use crate::core::{HttpError, HttpErrorType};
 pub struct StatusCode(pub u16);
 
 impl StatusCode {
+    /// Constructs a status code, checking that it is in the 100-599 range
+    ///
+    /// Use `StatusCode::from` for trusted values. There is no `TryFrom`, because it would conflict with `From`
+    /// # Example
+    /// ```
+    /// # use dhttp::reqres::StatusCode;
+    /// assert_eq!(StatusCode::new(404).unwrap().0, 404);
+    /// assert!(StatusCode::new(1000).is_err());
+    /// ```
+    pub fn new(code: u16) -> Result<StatusCode, InvalidStatusCode> {
+        if (100..600).contains(&code) {
+            Ok(StatusCode(code))
+        } else {
+            Err(InvalidStatusCode(code))
+        }
+    }
+
     /// Provides a textual representation of this status code
     /// # Example
     /// ```
     /// # use dhttp::reqres::StatusCode;
     /// assert_eq!(StatusCode(200).as_str(), "OK");
     /// assert_eq!(StatusCode(404).as_str(), "Not found");
+    /// assert_eq!(StatusCode(418).as_str(), "Client error");
     /// ```
     pub fn as_str(&self) -> &'static str {
         match self.0 {
+            101 => "Switching protocols",
+            103 => "Early hints",
             200 => "OK",
+            204 => "No content",
             206 => "Partial content",
             301 => "Moved permanently",
+            302 => "Found",
+            303 => "See other",
             304 => "Not modified",
+            307 => "Temporary redirect",
+            308 => "Permanent redirect",
             400 => "Bad request",
             401 => "Unauthorized",
             403 => "Forbidden",
             404 => "Not found",
             405 => "Method not allowed",
+            408 => "Request timeout",
             413 => "Request entity too large",
+            414 => "URI too long",
+            415 => "Unsupported media type",
             416 => "Range not satisfiable",
+            431 => "Request header fields too large",
             500 => "Internal server error",
+            501 => "Not implemented",
             505 => "HTTP version not supported",
+            // Less common codes get the name of their class
+            100..=199 => "Informational",
+            200..=299 => "Success",
+            300..=399 => "Redirection",
+            400..=499 => "Client error",
+            500..=599 => "Server error",
             _ => "Unknown",
         }
     }
 }
 
 impl StatusCode {
+    // 1xx
+
+    /// 101
+    pub const SWITCHING_PROTOCOLS: StatusCode = StatusCode(101);
+    /// 103
+    pub const EARLY_HINTS: StatusCode = StatusCode(103);
+
     // 2xx
 
     /// 200
     pub const OK: StatusCode = StatusCode(200);
+    /// 204
+    pub const NO_CONTENT: StatusCode = StatusCode(204);
     /// 206
     pub const PARTIAL_CONTENT: StatusCode = StatusCode(206);
 
@@ -47,8 +92,16 @@ impl StatusCode {
 
     /// 301
     pub const MOVED_PERMANENTLY: StatusCode = StatusCode(301);
+    /// 302
+    pub const FOUND: StatusCode = StatusCode(302);
+    /// 303
+    pub const SEE_OTHER: StatusCode = StatusCode(303);
     /// 304
     pub const NOT_MODIFIED: StatusCode = StatusCode(304);
+    /// 307
+    pub const TEMPORARY_REDIRECT: StatusCode = StatusCode(307);
+    /// 308
+    pub const PERMANENT_REDIRECT: StatusCode = StatusCode(308);
 
     // 4xx
 
@@ -62,15 +115,25 @@ impl StatusCode {
     pub const NOT_FOUND: StatusCode = StatusCode(404);
     /// 405
     pub const METHOD_NOT_ALLOWED: StatusCode = StatusCode(405);
+    /// 408
+    pub const REQUEST_TIMEOUT: StatusCode = StatusCode(408);
     /// 413
     pub const REQUEST_ENTITY_TOO_LARGE: StatusCode = StatusCode(413);
+    /// 414
+    pub const URI_TOO_LONG: StatusCode = StatusCode(414);
+    /// 415
+    pub const UNSUPPORTED_MEDIA_TYPE: StatusCode = StatusCode(415);
     /// 416
     pub const RANGE_NOT_SATISFIABLE: StatusCode = StatusCode(416);
+    /// 431
+    pub const REQUEST_HEADER_FIELDS_TOO_LARGE: StatusCode = StatusCode(431);
 
     // 5xx
 
     /// 500
     pub const INTERNAL_SERVER_ERROR: StatusCode = StatusCode(500);
+    /// 501
+    pub const NOT_IMPLEMENTED: StatusCode = StatusCode(501);
     /// 505
     pub const HTTP_VERSION_NOT_SUPPORTED: StatusCode = StatusCode(505);
 }
@@ -81,6 +144,18 @@ impl fmt::Display for StatusCode {
     }
 }
 
+impl From<u16> for StatusCode {
+    fn from(code: u16) -> StatusCode {
+        StatusCode(code)
+    }
+}
+
+impl From<StatusCode> for u16 {
+    fn from(code: StatusCode) -> u16 {
+        code.0
+    }
+}
+
 impl Error for StatusCode {}
 
 impl HttpError for StatusCode {
@@ -92,3 +167,15 @@ impl HttpError for StatusCode {
         *self
     }
 }
+
+/// Error returned from [`StatusCode::new`] for codes outside of 100-599
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InvalidStatusCode(pub u16);
+
+impl fmt::Display for InvalidStatusCode {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "invalid status code {}", self.0)
+    }
+}
+
+impl Error for InvalidStatusCode {}
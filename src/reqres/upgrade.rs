@@ -0,0 +1,56 @@
+//! Protocol upgrades (`101 Switching Protocols`)
+//! # Example
+//! ```
+//! use dhttp::reqres::res;
+//!
+//! # use std::io;
+//! # use tokio::io::AsyncWriteExt;
+//! # use dhttp::reqres::upgrade::HttpUpgrade;
+//! # use dhttp::core::connection::HttpConnection;
+//! struct Shout;
+//! impl HttpUpgrade for Shout {
+//!     async fn upgrade(&mut self, conn: &mut dyn HttpConnection) -> io::Result<()> {
+//!         conn.write_all(b"HELLO FROM MY PROTOCOL\n").await
+//!     }
+//! }
+//! # use dhttp::core::{HttpService, HttpResult};
+//! # use dhttp::reqres::{HttpRequest, StatusCode};
+//! # use dhttp::core::connection::HttpRead;
+//! struct MyService;
+//! impl HttpService for MyService {
+//!     async fn request(&self, _route: &str, req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+//!         if !req.wants_upgrade("shout") { return Err(StatusCode::BAD_REQUEST.into()); }
+//!         Ok(res::upgrade("shout", Shout))
+//!     }
+//! }
+//! ```
+
+use std::io;
+use std::pin::Pin;
+
+use crate::core::connection::HttpConnection;
+
+/// Handler of an upgraded connection
+///
+/// Can be used through [`res::upgrade`]
+///
+/// [`res::upgrade`]: crate::reqres::res::upgrade
+pub trait HttpUpgrade: Send + 'static {
+    /// Takes over the connection after `101 Switching Protocols` was sent.
+    /// Connection is closed after it returns
+    ///
+    /// Equivalent signature: `async fn upgrade(&mut self, conn: &mut dyn HttpConnection) -> io::Result<()>`
+    fn upgrade(&mut self, conn: &mut dyn HttpConnection) -> impl Future<Output = io::Result<()>> + Send;
+}
+
+/// Dyn version of [`HttpUpgrade`]
+pub trait HttpUpgradeRaw: Send {
+    /// Dyn version of `upgrade`
+    fn upgrade_raw<'a>(&'a mut self, conn: &'a mut dyn HttpConnection) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send + 'a>>;
+}
+
+impl<T: HttpUpgrade> HttpUpgradeRaw for T {
+    fn upgrade_raw<'a>(&'a mut self, conn: &'a mut dyn HttpConnection) -> Pin<Box<dyn Future<Output = io::Result<()>> + Send + 'a>> {
+        Box::pin(self.upgrade(conn))
+    }
+}
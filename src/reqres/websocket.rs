@@ -0,0 +1,339 @@
+//! WebSocket (RFC 6455) on top of [`HttpUpgrade`]
+//! # Example
+//! ```
+//! # use std::io;
+//! # use dhttp::reqres::websocket::{self, HttpWebSocket, WebSocket, Message};
+//! struct Echo;
+//! impl HttpWebSocket for Echo {
+//!     async fn run(&mut self, ws: &mut WebSocket<'_>) -> io::Result<()> {
+//!         while let Some(msg) = ws.recv().await? {
+//!             match msg {
+//!                 Message::Text(_) | Message::Binary(_) => ws.send(msg).await?,
+//!                 _ => {}
+//!             }
+//!         }
+//!         Ok(())
+//!     }
+//! }
+//! # use dhttp::core::{HttpService, HttpResult};
+//! # use dhttp::reqres::HttpRequest;
+//! # use dhttp::core::connection::HttpRead;
+//! struct MyService;
+//! impl HttpService for MyService {
+//!     async fn request(&self, _route: &str, req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+//!         websocket::upgrade(req, Echo)
+//!     }
+//! }
+//! ```
+
+use std::io::{self, ErrorKind};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::core::HttpResult;
+use crate::core::connection::HttpConnection;
+use crate::reqres::{res, HttpRequest, HttpMethod, StatusCode};
+use crate::reqres::upgrade::HttpUpgrade;
+
+/// Appended to the client key, see RFC 6455 section 1.3
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Messages bigger than this close the connection, unless changed with [`WebSocket::max_message_size`]
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 << 20; // 16MB
+
+/// Single WebSocket message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// Answered with a [`Pong`](Message::Pong) automatically
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// Close code and reason, if the peer sent them
+    Close(Option<(u16, String)>),
+}
+
+impl Message {
+    fn opcode(&self) -> u8 {
+        match self {
+            Message::Text(_) => 0x1,
+            Message::Binary(_) => 0x2,
+            Message::Close(_) => 0x8,
+            Message::Ping(_) => 0x9,
+            Message::Pong(_) => 0xA,
+        }
+    }
+}
+
+/// Handler of a WebSocket connection
+///
+/// Can be used through [`upgrade`]
+pub trait HttpWebSocket: Send + 'static {
+    /// Runs after the handshake. Connection is closed after it returns
+    ///
+    /// Equivalent signature: `async fn run(&mut self, ws: &mut WebSocket<'_>) -> io::Result<()>`
+    fn run(&mut self, ws: &mut WebSocket<'_>) -> impl Future<Output = io::Result<()>> + Send;
+}
+
+/// Answers a WebSocket handshake, then runs `handler` on the connection
+///
+/// Fires a `400` if it is not a valid handshake: it must be a `GET` with `Upgrade: websocket`,
+/// `Sec-WebSocket-Version: 13` and a `Sec-WebSocket-Key`
+pub fn upgrade(req: &HttpRequest, handler: impl HttpWebSocket) -> HttpResult {
+    let key = req.get_header("Sec-WebSocket-Key").map(str::trim).unwrap_or_default();
+    let valid = req.method == HttpMethod::Get
+        && req.wants_upgrade("websocket")
+        && req.get_header("Sec-WebSocket-Version").is_some_and(|version| version.trim() == "13")
+        // base64 of 16 bytes
+        && key.len() == 24;
+    if !valid {
+        return Err(StatusCode::BAD_REQUEST.into());
+    }
+
+    let mut res = res::upgrade("websocket", Handler(handler));
+    res.add_header("Sec-WebSocket-Accept", accept_key(key));
+    Ok(res)
+}
+
+/// `Sec-WebSocket-Accept` for a given `Sec-WebSocket-Key`
+pub fn accept_key(key: &str) -> String {
+    let mut data = key.as_bytes().to_vec();
+    data.extend_from_slice(GUID.as_bytes());
+    crate::util::base64(&crate::util::sha1(&data))
+}
+
+struct Handler<T>(T);
+
+impl<T: HttpWebSocket> HttpUpgrade for Handler<T> {
+    async fn upgrade(&mut self, conn: &mut dyn HttpConnection) -> io::Result<()> {
+        let mut ws = WebSocket::new(conn);
+        self.0.run(&mut ws).await?;
+        // Closing handshake, if handler didn't do it
+        if !ws.close_sent { ws.close(1000, "").await?; }
+        Ok(())
+    }
+}
+
+/// Message-level reader and writer of a WebSocket connection
+///
+/// Fragmented messages are joined back, pings are answered. Protocol violations
+/// (unmasked client frames, invalid UTF-8 in text, oversized messages) fail with `InvalidData`
+pub struct WebSocket<'a> {
+    conn: &'a mut dyn HttpConnection,
+    max_message_size: usize,
+    close_sent: bool,
+    close_received: bool,
+    /// Opcode and payload of a fragmented message
+    partial: Option<(u8, Vec<u8>)>,
+}
+
+impl<'a> WebSocket<'a> {
+    /// Wraps a connection which already finished the handshake
+    pub fn new(conn: &'a mut dyn HttpConnection) -> WebSocket<'a> {
+        WebSocket { conn, max_message_size: DEFAULT_MAX_MESSAGE_SIZE, close_sent: false, close_received: false, partial: None }
+    }
+
+    /// Messages bigger than this fail with `InvalidData`, 16 MiB by default
+    pub fn max_message_size(&mut self, max_message_size: usize) -> &mut Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Receives the next message, `None` after the connection was closed
+    ///
+    /// [`Close`](Message::Close) is answered automatically, and it is the last message
+    pub async fn recv(&mut self) -> io::Result<Option<Message>> {
+        if self.close_received { return Ok(None); }
+
+        loop {
+            let (fin, opcode, payload) = match self.read_frame().await {
+                Ok(frame) => frame,
+                // Peer went away without a close frame
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof && self.partial.is_none() => return Ok(None),
+                Err(e) => return Err(e),
+            };
+
+            let (opcode, payload) = match opcode {
+                // Control frames can come in the middle of a fragmented message
+                0x8..=0xA => (opcode, payload),
+                0x0 => {
+                    let Some((_, data)) = &mut self.partial else { return Err(invalid("unexpected continuation frame")) };
+                    if data.len() + payload.len() > self.max_message_size { return Err(invalid("message too big")); }
+                    data.extend_from_slice(&payload);
+                    if !fin { continue; }
+                    self.partial.take().unwrap()
+                }
+                0x1 | 0x2 => {
+                    if self.partial.is_some() { return Err(invalid("expected continuation frame")); }
+                    if !fin {
+                        self.partial = Some((opcode, payload));
+                        continue;
+                    }
+                    (opcode, payload)
+                }
+                _ => return Err(invalid("unknown opcode")),
+            };
+
+            let msg = match opcode {
+                0x1 => Message::Text(String::from_utf8(payload).map_err(|_| invalid("text is not UTF-8"))?),
+                0x2 => Message::Binary(payload),
+                0x8 => {
+                    self.close_received = true;
+                    let close = match payload.len() {
+                        0 => None,
+                        1 => return Err(invalid("bad close frame")),
+                        _ => {
+                            let code = u16::from_be_bytes([payload[0], payload[1]]);
+                            let reason = String::from_utf8(payload[2..].to_vec()).map_err(|_| invalid("text is not UTF-8"))?;
+                            Some((code, reason))
+                        }
+                    };
+                    if !self.close_sent {
+                        // Echo the code back
+                        let code = close.as_ref().map(|&(code, _)| code).unwrap_or(1000);
+                        self.close(code, "").await?;
+                    }
+                    Message::Close(close)
+                }
+                0x9 => {
+                    if !self.close_sent { self.write_frame(0xA, &payload).await?; }
+                    Message::Ping(payload)
+                }
+                _ => Message::Pong(payload),
+            };
+            return Ok(Some(msg));
+        }
+    }
+
+    /// Sends a message as a single frame
+    pub async fn send(&mut self, msg: Message) -> io::Result<()> {
+        let opcode = msg.opcode();
+        match msg {
+            Message::Text(text) => self.write_frame(opcode, text.as_bytes()).await,
+            Message::Binary(data) | Message::Ping(data) | Message::Pong(data) => self.write_frame(opcode, &data).await,
+            Message::Close(close) => {
+                let (code, reason) = close.unwrap_or((1000, String::new()));
+                self.close(code, &reason).await
+            }
+        }
+    }
+
+    /// Starts the closing handshake, the peer answers with its own [`Close`](Message::Close)
+    pub async fn close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+        if self.close_sent { return Ok(()); }
+        let mut payload = code.to_be_bytes().to_vec();
+        // Control frames are limited to 125 bytes
+        let mut end = reason.len().min(123);
+        while !reason.is_char_boundary(end) { end -= 1; }
+        payload.extend_from_slice(&reason.as_bytes()[..end]);
+        self.write_frame(0x8, &payload).await?;
+        self.close_sent = true;
+        Ok(())
+    }
+
+    async fn read_frame(&mut self) -> io::Result<(bool, u8, Vec<u8>)> {
+        let mut header = [0; 2];
+        self.conn.read_exact(&mut header).await?;
+        let fin = header[0] & 0x80 != 0;
+        let opcode = header[0] & 0x0F;
+        // No extensions are negotiated
+        if header[0] & 0x70 != 0 { return Err(invalid("reserved bits are set")); }
+        if header[1] & 0x80 == 0 { return Err(invalid("client frame is not masked")); }
+
+        let len = match header[1] & 0x7F {
+            126 => self.conn.read_u16().await? as u64,
+            127 => self.conn.read_u64().await?,
+            len => len as u64,
+        };
+        if opcode >= 0x8 && (!fin || len > 125) { return Err(invalid("bad control frame")); }
+        if len > self.max_message_size as u64 { return Err(invalid("message too big")); }
+
+        let mut mask = [0; 4];
+        self.conn.read_exact(&mut mask).await?;
+        let mut payload = vec![0; len as usize];
+        self.conn.read_exact(&mut payload).await?;
+        for (i, b) in payload.iter_mut().enumerate() {
+            *b ^= mask[i % 4];
+        }
+        Ok((fin, opcode, payload))
+    }
+
+    async fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        // Server frames are not masked
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            len @ 0..126 => frame.push(len as u8),
+            len @ 126..65536 => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+        self.conn.write_all(&frame).await?;
+        self.conn.flush().await
+    }
+}
+
+fn invalid(msg: &'static str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+
+    use super::{accept_key, Message, WebSocket};
+
+    /// Client frame, masked with a fixed key
+    fn frame(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [1, 2, 3, 4];
+        let mut out = vec![first, 0x80 | payload.len() as u8];
+        out.extend_from_slice(&mask);
+        out.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        out
+    }
+
+    #[test]
+    fn handshake() {
+        // RFC 6455 section 1.3
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn frames() {
+        crate::server::tokio_rt().unwrap().block_on(async {
+            let (mut client, server) = tokio::io::duplex(1024);
+            let mut server = BufReader::new(server);
+            let mut ws = WebSocket::new(&mut server);
+
+            // Fragmented text with a ping in between
+            let mut input = frame(0x01, b"hel");
+            input.extend(frame(0x89, b"p"));
+            input.extend(frame(0x80, b"lo"));
+            input.extend(frame(0x88, &[0x03, 0xE8]));
+            client.write_all(&input).await.unwrap();
+
+            assert_eq!(ws.recv().await.unwrap(), Some(Message::Ping(b"p".to_vec())));
+            assert_eq!(ws.recv().await.unwrap(), Some(Message::Text("hello".to_string())));
+            ws.send(Message::Binary(vec![7; 200])).await.unwrap();
+            assert_eq!(ws.recv().await.unwrap(), Some(Message::Close(Some((1000, String::new())))));
+            assert_eq!(ws.recv().await.unwrap(), None);
+
+            let mut output = [0; 3 + 4 + 200 + 4];
+            client.read_exact(&mut output).await.unwrap();
+            assert_eq!(&output[..3], &[0x8A, 1, b'p']);
+            assert_eq!(&output[3..7], &[0x82, 126, 0, 200]);
+            assert_eq!(&output[207..], &[0x88, 2, 0x03, 0xE8]);
+
+            // Unmasked frame
+            let (mut client, server) = tokio::io::duplex(1024);
+            let mut server = BufReader::new(server);
+            client.write_all(&[0x81, 0x01, b'a']).await.unwrap();
+            assert!(WebSocket::new(&mut server).recv().await.is_err());
+        });
+    }
+}
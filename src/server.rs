@@ -1,31 +1,145 @@
 //! HTTP server
 
 use std::io;
+use std::fmt;
+use std::error::Error;
 use std::sync::Arc;
-use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::pin::pin;
 use std::time::Duration;
 
 use tokio::io::{BufReader, AsyncReadExt, AsyncWriteExt, AsyncBufReadExt};
-use tokio::net::TcpSocket;
-use socket2::SockRef;
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+#[cfg(unix)]
+use std::path::Path;
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+use tokio::time::Instant;
+use tokio::task::JoinSet;
+use socket2::{SockRef, TcpKeepalive};
 
 use crate::h1::{self, HttpRequestError};
-use crate::reqres::{HttpRequest, HttpBody, StatusCode};
-use crate::core::{HttpService, HttpServiceRaw, HttpErrorHandler, HttpErrorType, HttpLogger};
-use crate::core::connection::{HttpConnection, EmitContinue};
+use crate::reqres::{HttpRequest, HttpResponse, HttpHeader, HttpBody, HttpMethod, StatusCode};
+use crate::core::{HttpService, HttpServiceRaw, HttpError, HttpErrorHandler, HttpErrorMapper, HttpErrorType, HttpGuard, HttpLogger, HttpProgress, HttpRead, HttpResult};
+use crate::core::connection::{HttpConnection, EmitContinue, ChunkedBody, Progress, MinRate};
 use crate::service::{DefaultService, DefaultLogger, ErrorPageHandler};
-use crate::util::future::Or;
+use crate::util::future::{Or, Ordered, now_or_never};
+use crate::util::ipfilter::{Cidr, IpFilter};
+use crate::util::path;
 
 const DEFAULT_MAX_HEADERS_SIZE: u64 = 65536; // 64KB
+const DEFAULT_MAX_HEADER_LINE_SIZE: usize = 8192; // 8KB
+const DEFAULT_MAX_RESPONSE_HEADERS_SIZE: usize = 262144; // 256KB
+const DEFAULT_MAX_BODY_SIZE: u64 = 64 << 20; // 64MB
+const DEFAULT_MAX_CHUNK_SIZE: u64 = 16 << 20; // 16MB
+const DEFAULT_PIPELINE_CONCURRENCY: usize = 1; // sequential
+const DEFAULT_ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+const DEFAULT_MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(5);
+const DEFAULT_TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+const DEFAULT_STREAM_BUFFER_SIZE: usize = 16384; // 16KB
+const DEFAULT_HEADER_READ_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(75);
 
 /// An HTTP/1.1 server
 #[non_exhaustive]
 pub struct HttpServer {
     pub name: String,
     pub max_headers_size: u64,
+    /// Maximum length of the request line or a single header line
+    ///
+    /// Longer request line is rejected with `414 URI too long`, header with `431 Request header fields too large`
+    pub max_header_line_size: usize,
+    /// Responses with bigger headers are replaced with `500 Internal server error`
+    pub max_response_headers_size: usize,
+    /// Longest request body, `413 Request entity too large` is sent for bigger ones
+    ///
+    /// `Content-Length` is checked before the service runs. Chunked bodies have no length in advance,
+    /// so reading one fails with the same `413` once more than this was decoded.
+    /// Services may have a lower limit of their own
+    pub max_body_size: u64,
+    /// Longest single chunk of a chunked request body, bigger ones fail the read with `413`
+    pub max_chunk_size: u64,
+    /// Keeps trailer fields of chunked request bodies for [`HttpRequest::trailers`], they are dropped by default
+    pub capture_trailers: bool,
     pub service: Box<dyn HttpServiceRaw>,
     pub error_handler: Box<dyn HttpErrorHandler>,
+    /// Tried in order before the error handler
+    pub error_mappers: Vec<Box<dyn HttpErrorMapper>>,
     pub logger: Box<dyn HttpLogger>,
+    /// How many pipelined requests without a body can be served at once
+    ///
+    /// Responses are buffered and sent in the request order. Higher values use more memory
+    pub pipeline_concurrency: usize,
+    /// Whether `100 Continue` is sent as soon as the service reads the body
+    ///
+    /// When disabled, service has to call [`accept_body`] first. Otherwise, client uploads the body
+    /// only after its own timeout
+    ///
+    /// [`accept_body`]: crate::core::connection::accept_body
+    pub implicit_continue: bool,
+    /// Delay after the first failed `accept()`, doubles on each next failure
+    pub accept_backoff: Duration,
+    /// Maximum delay between failed `accept()` calls
+    pub max_accept_backoff: Duration,
+    /// Connections over this limit are closed right after accepting (unlimited if `None`)
+    pub max_connections: Option<usize>,
+    /// Observes body transfers, see [`HttpProgress`]
+    pub progress: Option<Box<dyn HttpProgress>>,
+    /// Minimum rate (bytes per second) at which clients have to receive files, disabled if `None`
+    ///
+    /// Protects from slow reading clients. It is enforced only after first 10 seconds of the transfer
+    pub min_response_rate: Option<u64>,
+    /// Connections from addresses it doesn't allow are closed before reading anything
+    pub ip_filter: IpFilter,
+    /// Idle time before TCP keepalive probes are sent, and interval between them (disabled if `None`)
+    ///
+    /// Detects dead peers, so their connections don't hang forever. The interval is ignored on platforms
+    /// which don't support it (e.g. OpenBSD), OS default is used there
+    pub tcp_keepalive: Option<Duration>,
+    /// `SO_LINGER` of accepted connections, OS default if `None`
+    ///
+    /// With zero duration, closed connections are reset instead of being shut down gracefully
+    pub linger: Option<Duration>,
+    /// Maximum size of a chunk of streamed body ([`HttpBody::Stream`])
+    ///
+    /// Small reads which are immediately ready are merged into one chunk up to this size,
+    /// data is never held back waiting for more. SSE events are always sent as is
+    pub stream_buffer_size: usize,
+    /// Headers added to every response, unless service has set them already
+    ///
+    /// `Server` header is added from [`name`](HttpServer::name) the same way
+    pub default_headers: Vec<HttpHeader>,
+    /// Checks every request before the service, see [`HttpGuard`]
+    pub guard: Option<Box<dyn HttpGuard>>,
+    /// Connections older than this are closed after finishing the current request (unlimited if `None`)
+    ///
+    /// Makes clients reconnect periodically, so load balancers can spread them again
+    pub max_connection_duration: Option<Duration>,
+    /// On shutdown, how long open connections may take to finish before they are aborted
+    ///
+    /// If `None`, [`serve_tcp`] returns right away and open connections are left to the runtime
+    pub drain_timeout: Option<Duration>,
+    /// Addresses of reverse proxies in front of this server, empty by default
+    ///
+    /// Requests from them may tell their original scheme with `Forwarded` or `X-Forwarded-Proto`,
+    /// see [`HttpRequest::scheme`]
+    pub trusted_proxies: Vec<Cidr>,
+    /// Rejects requests which don't follow RFC 9112 exactly, like bare LF line endings
+    /// or whitespace before the colon of a header. Disabled by default
+    ///
+    /// Clients which are lenient themselves may be accepted by one server and rejected by another,
+    /// which proxies can be tricked with. All checks are listed in the docs of the parser (`h1::parse`)
+    pub strict_parsing: bool,
+    /// Methods listed in `Allow` of the response to `OPTIONS *`, which asks about the whole server
+    pub server_methods: Vec<HttpMethod>,
+    /// How long the whole request head may take to arrive, `408 Request timeout` closes the connection after it
+    ///
+    /// Protects from clients sending headers byte by byte (slowloris). For the first request of a connection,
+    /// it counts from the accept
+    pub header_read_timeout: Duration,
+    /// How long a kept-alive connection may stay idle between requests, it is closed silently after it
+    pub keep_alive_timeout: Duration,
 }
 
 impl HttpServer {
@@ -33,9 +147,35 @@ impl HttpServer {
         HttpServer {
             name: "DrakoHTTP".to_string(),
             max_headers_size: DEFAULT_MAX_HEADERS_SIZE,
+            max_header_line_size: DEFAULT_MAX_HEADER_LINE_SIZE,
+            max_response_headers_size: DEFAULT_MAX_RESPONSE_HEADERS_SIZE,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            max_chunk_size: DEFAULT_MAX_CHUNK_SIZE,
+            capture_trailers: false,
             service: Box::new(DefaultService),
-            error_handler: Box::new(ErrorPageHandler { name: "DrakoHTTP".to_string() }),
+            error_handler: Box::new(ErrorPageHandler::new("DrakoHTTP")),
+            error_mappers: vec![],
             logger: Box::new(DefaultLogger),
+            pipeline_concurrency: DEFAULT_PIPELINE_CONCURRENCY,
+            implicit_continue: true,
+            accept_backoff: DEFAULT_ACCEPT_BACKOFF,
+            max_accept_backoff: DEFAULT_MAX_ACCEPT_BACKOFF,
+            max_connections: None,
+            progress: None,
+            min_response_rate: None,
+            ip_filter: IpFilter::new(),
+            tcp_keepalive: Some(DEFAULT_TCP_KEEPALIVE),
+            linger: None,
+            stream_buffer_size: DEFAULT_STREAM_BUFFER_SIZE,
+            default_headers: vec![],
+            guard: None,
+            max_connection_duration: None,
+            drain_timeout: None,
+            trusted_proxies: vec![],
+            strict_parsing: false,
+            server_methods: vec![HttpMethod::Get, HttpMethod::Head, HttpMethod::Post, HttpMethod::Options],
+            header_read_timeout: DEFAULT_HEADER_READ_TIMEOUT,
+            keep_alive_timeout: DEFAULT_KEEP_ALIVE_TIMEOUT,
         }
     }
 
@@ -49,10 +189,128 @@ impl HttpServer {
         self
     }
 
+    /// Adds an error mapper, see [`HttpErrorMapper`]
+    pub fn map_error(&mut self, mapper: impl HttpErrorMapper) -> &mut Self {
+        self.error_mappers.push(Box::new(mapper));
+        self
+    }
+
     pub fn logger(&mut self, logger: impl HttpLogger) -> &mut Self {
         self.logger = Box::new(logger);
         self
     }
+
+    pub fn max_body_size(&mut self, max_body_size: u64) -> &mut Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    pub fn max_chunk_size(&mut self, max_chunk_size: u64) -> &mut Self {
+        self.max_chunk_size = max_chunk_size;
+        self
+    }
+
+    pub fn capture_trailers(&mut self, capture_trailers: bool) -> &mut Self {
+        self.capture_trailers = capture_trailers;
+        self
+    }
+
+    pub fn pipeline_concurrency(&mut self, pipeline_concurrency: usize) -> &mut Self {
+        self.pipeline_concurrency = pipeline_concurrency;
+        self
+    }
+
+    pub fn implicit_continue(&mut self, implicit_continue: bool) -> &mut Self {
+        self.implicit_continue = implicit_continue;
+        self
+    }
+
+    pub fn accept_backoff(&mut self, accept_backoff: Duration, max_accept_backoff: Duration) -> &mut Self {
+        self.accept_backoff = accept_backoff;
+        self.max_accept_backoff = max_accept_backoff;
+        self
+    }
+
+    pub fn max_connections(&mut self, max_connections: usize) -> &mut Self {
+        self.max_connections = Some(max_connections);
+        self
+    }
+
+    pub fn progress(&mut self, progress: impl HttpProgress) -> &mut Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    pub fn min_response_rate(&mut self, min_response_rate: u64) -> &mut Self {
+        self.min_response_rate = Some(min_response_rate);
+        self
+    }
+
+    pub fn ip_filter(&mut self, ip_filter: IpFilter) -> &mut Self {
+        self.ip_filter = ip_filter;
+        self
+    }
+
+    pub fn tcp_keepalive(&mut self, tcp_keepalive: Option<Duration>) -> &mut Self {
+        self.tcp_keepalive = tcp_keepalive;
+        self
+    }
+
+    pub fn linger(&mut self, linger: Duration) -> &mut Self {
+        self.linger = Some(linger);
+        self
+    }
+
+    pub fn stream_buffer_size(&mut self, stream_buffer_size: usize) -> &mut Self {
+        self.stream_buffer_size = stream_buffer_size;
+        self
+    }
+
+    pub fn guard(&mut self, guard: impl HttpGuard) -> &mut Self {
+        self.guard = Some(Box::new(guard));
+        self
+    }
+
+    pub fn max_connection_duration(&mut self, max_connection_duration: Duration) -> &mut Self {
+        self.max_connection_duration = Some(max_connection_duration);
+        self
+    }
+
+    pub fn drain_timeout(&mut self, drain_timeout: Duration) -> &mut Self {
+        self.drain_timeout = Some(drain_timeout);
+        self
+    }
+
+    pub fn trusted_proxy(&mut self, proxy: Cidr) -> &mut Self {
+        self.trusted_proxies.push(proxy);
+        self
+    }
+
+    pub fn strict_parsing(&mut self, strict_parsing: bool) -> &mut Self {
+        self.strict_parsing = strict_parsing;
+        self
+    }
+
+    pub fn server_methods(&mut self, server_methods: Vec<HttpMethod>) -> &mut Self {
+        self.server_methods = server_methods;
+        self
+    }
+
+    pub fn header_read_timeout(&mut self, header_read_timeout: Duration) -> &mut Self {
+        self.header_read_timeout = header_read_timeout;
+        self
+    }
+
+    pub fn keep_alive_timeout(&mut self, keep_alive_timeout: Duration) -> &mut Self {
+        self.keep_alive_timeout = keep_alive_timeout;
+        self
+    }
+
+    /// Adds a header to every response, see [`default_headers`](HttpServer::default_headers)
+    pub fn default_header(&mut self, name: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.default_headers.push(HttpHeader { name: name.into(), value: value.into() });
+        self
+    }
 }
 
 impl Default for HttpServer {
@@ -62,18 +320,24 @@ impl Default for HttpServer {
 }
 
 impl HttpServer {
-    async fn handle_connection(&self, mut conn: impl HttpConnection, addr: SocketAddr) -> io::Result<()> {
+    pub(crate) async fn handle_connection(&self, mut conn: impl HttpConnection, info: ConnInfo) -> io::Result<()> {
+        // Request that was read ahead while collecting a pipelined batch
+        let mut pending = None;
+        let deadline = self.max_connection_duration.map(|duration| Instant::now() + duration);
         let mut connection_close = false;
         while !connection_close {
-            let req = h1::read((&mut conn).take(self.max_headers_size)).await;
+            let req = match pending.take() {
+                Some(req) => req,
+                None => self.read_head(&mut conn).await,
+            };
             if let Err(err) = req {
                 if let HttpRequestError::Io(err) = err {
                     // IO errors should not be handled
                     return Err(err);
                 } else {
                     // Could not parse request, return Bad request
-                    let mut res = self.error_handler.plain_code(StatusCode::BAD_REQUEST);
-                    h1::send(&HttpRequest::default(), &mut res, &mut conn).await?;
+                    let mut res = self.error_handler.plain_code(err.status_code());
+                    h1::send(&HttpRequest::default(), &mut res, &mut conn, None, self.stream_buffer_size).await?;
                     return conn.shutdown().await;
                 }
             }
@@ -81,100 +345,484 @@ impl HttpServer {
             let mut req = req.unwrap();
 
             // Address has to be set by the connection handler
-            req.addr = addr.ip().to_canonical();
+            info.apply(&mut req);
+            ignore_h2c(&mut req);
 
-            // HTTP/2 prior knowledge headers look like `PRI * HTTP/2.0`
-            // These connections are not supported
+            // HTTP/2 prior knowledge preface starts with `PRI * HTTP/2.0`, its frames follow right away.
+            // These connections are not supported, so the client gets a `505` it can read as HTTP/1.1
+            // and falls back. HTTP/2 support would take over the connection here
             if req.version.major != 1 {
                 let mut res = self.error_handler.plain_code(StatusCode::HTTP_VERSION_NOT_SUPPORTED);
-                h1::send(&req, &mut res, &mut conn).await?;
+                h1::send(&req, &mut res, &mut conn, None, self.stream_buffer_size).await?;
                 return conn.shutdown().await;
             }
 
+            // Requests without a body don't touch the connection while being served,
+            // so the ones that are already pipelined after it can be served concurrently
+            if self.pipeline_concurrency > 1 && batchable(&req) {
+                let mut batch = vec![req];
+                // Only heads that have fully arrived join, waiting for the rest would hold back the finished responses.
+                // Reading a buffered head doesn't wait
+                while batch.len() < self.pipeline_concurrency
+                    && matches!(now_or_never(conn.fill_buf()).await, Some(Ok(buf)) if h1::head_complete(buf))
+                {
+                    match self.read_head(&mut conn).await {
+                        Ok(mut next) => {
+                            ignore_h2c(&mut next);
+                            if next.version.major != 1 || !batchable(&next) {
+                                pending = Some(Ok(next));
+                                break;
+                            }
+                            info.apply(&mut next);
+                            batch.push(next);
+                        }
+                        // Everything else goes through the usual path
+                        Err(err) => {
+                            pending = Some(Err(err));
+                            break;
+                        }
+                    }
+                }
+                connection_close = self.handle_batch(batch, &mut conn, deadline).await?;
+                // Sent before waiting for the next request
+                if !connection_close { conn.flush().await?; }
+                if !connection_close && pending.is_none() && !self.next_request(&mut conn).await? { connection_close = true; }
+                continue;
+            }
+
             // Before starting file upload, curl expects server to send `100 Continue` response
             // Otherwise, it will wait for a timeout
             // This adapter echoes `100 Continue` when service starts reading the body
-            // (meaning, that service has accepted it)
+            // (meaning, that service has accepted it), or only on `accept_body` if not implicit
+            // Chunked body is framed by the decoder instead
+            let limit = if req.chunked { u64::MAX } else { req.len };
             let mut body = EmitContinue {
-                conn: (&mut conn).take(req.len),
+                conn: (&mut conn).take(limit),
                 to_send: b"",
+                implicit: self.implicit_continue,
             };
-            if req.cmp_header("Expect", "100-continue") {
+            // Empty body has nothing to continue with
+            if req.cmp_header("Expect", "100-continue") && (req.len > 0 || req.chunked) {
                 body.to_send = b"HTTP/1.1 100 Continue\r\n\r\n";
             }
 
             // Future TODO: HTTP/1.1 connection handler has a lot of hardcoded functionality
             // that still applies to HTTP/2 and QUIC. Some logic here could be separated
 
-            // Before executing the service, we have to check if request is compatible
-            // This is connection handler's responsibility
-            let mut res = match self.service.filter_raw(&req.route, &req) {
-                Ok(()) => self.service.request_raw(&req.route, &req, &mut body).await,
-                Err(err) => Err(err),
+            let (res, body_consumed) = if req.chunked {
+                let mut chunked = ChunkedBody::new(&mut body, self.max_chunk_size, self.max_body_size);
+                if self.capture_trailers { chunked.capture_trailers(Arc::clone(&req.trailers)); }
+                let res = self.respond_tracked(&req, &mut chunked).await;
+                (res, chunked.is_done())
+            } else {
+                let res = self.respond_tracked(&req, &mut body).await;
+                (res, body.conn.limit() == 0)
+            };
+            let Some(mut res) = res else {
+                // IO error
+                return conn.shutdown().await;
             };
+            close_after_deadline(&mut res, deadline);
+            connection_close = self.finalize(&req, &mut res, body_consumed);
 
-            if let Ok(res) = &res {
+            // Now, send the response
+            self.send(&req, &mut res, &mut conn).await?;
+            if let HttpBody::Sse(_) = res.body {
+                connection_close = true;
+            }
+
+            // Check if there is nothing more to read
+            if !connection_close && !self.next_request(&mut conn).await? { connection_close = true; }
+        }
+        // Loop ended, we close the connection now
+        conn.shutdown().await
+    }
+
+    /// Reads the next request head, it has to arrive within [`header_read_timeout`](HttpServer::header_read_timeout)
+    async fn read_head(&self, conn: &mut impl HttpConnection) -> Result<HttpRequest, HttpRequestError> {
+        let read = h1::read(conn.take(self.max_headers_size), self.max_header_line_size, self.strict_parsing);
+        match tokio::time::timeout(self.header_read_timeout, read).await {
+            Ok(req) => req,
+            Err(_) => Err(HttpRequestError::Timeout),
+        }
+    }
+
+    /// Waits for the next request on a kept-alive connection
+    ///
+    /// Returns `false` if client has closed the connection or stayed idle for [`keep_alive_timeout`](HttpServer::keep_alive_timeout)
+    async fn next_request(&self, conn: &mut impl HttpConnection) -> io::Result<bool> {
+        match tokio::time::timeout(self.keep_alive_timeout, conn.fill_buf()).await {
+            Ok(buf) => Ok(!buf?.is_empty()),
+            Err(_) => Ok(false),
+        }
+    }
+
+    /// Runs [`respond`](HttpServer::respond), reporting the body to the progress observer
+    async fn respond_tracked(&self, req: &HttpRequest, body: &mut dyn HttpRead) -> Option<HttpResponse> {
+        match &self.progress {
+            Some(progress) => self.respond(req, &mut Progress::new(body, req, &**progress)).await,
+            None => self.respond(req, body).await,
+        }
+    }
+
+    /// Runs the service and handles its errors
+    ///
+    /// Returns `None` when the connection has to be terminated
+    async fn respond(&self, req: &HttpRequest, body: &mut dyn HttpRead) -> Option<HttpResponse> {
+        // `*` is not a route, it is answered by the server itself
+        if req.method == HttpMethod::Options && req.route == "*" {
+            let mut res = HttpResponse::new();
+            res.code = StatusCode::NO_CONTENT;
+            let methods: Vec<_> = self.server_methods.iter().map(HttpMethod::as_str).collect();
+            res.add_header("Allow", methods.join(", "));
+            self.logger.log(req, &res);
+            return Some(res);
+        }
+
+        // Before executing the service, we have to check if request is compatible
+        // This is connection handler's responsibility
+        // `/a//b` and `/a/./b` have to be routed as `/a/b`, `..` is not allowed
+        // `*` is only valid with `OPTIONS`, handled above
+        let route: HttpResult<_> = match req.route.as_str() {
+            "*" => Err(StatusCode::BAD_REQUEST.into()),
+            route => path::normalize(route).map_err(|_| StatusCode::BAD_REQUEST.into()),
+        };
+        let checked = route.and_then(|route| {
+            if req.len > self.max_body_size { return Err(StatusCode::REQUEST_ENTITY_TOO_LARGE.into()); }
+            if let Some(guard) = &self.guard { guard.check(&route, req)?; }
+            self.service.filter_raw(&route, req)?;
+            Ok(route)
+        });
+        let res = match checked {
+            Ok(route) => self.service.request_raw(&route, req, body).await,
+            Err(err) => Err(err),
+        };
+        // Clients reject huge headers anyway, it is most likely a bug
+        let res = match res {
+            Ok(res) if headers_size(&res) > self.max_response_headers_size => Err(ResponseHeadersTooLarge.into()),
+            res => res,
+        };
+
+        let err = match res {
+            Ok(res) => {
                 // Log request+response with our defined logger
-                self.logger.log(&req, res);
-            } else if let Err(err) = res {
-                // Response is Err, should be handled with defined error handler
-                let mut handled = match err.error_type() {
-                    // IO error
-                    HttpErrorType::Terminate => return conn.shutdown().await,
-                    // Status code
-                    HttpErrorType::Status => self.error_handler.plain_code(err.status_code()),
-                    // Error with description
-                    HttpErrorType::Full => self.error_handler.error(&req, err.as_ref()),
-                };
-                // Always use the original status code in the error response (connection handler sets this)
-                handled.code = err.status_code();
-                // Log the error
-                match err.error_type() {
-                    HttpErrorType::Terminate => unreachable!(),
-                    HttpErrorType::Status => self.logger.log(&req, &handled),
-                    HttpErrorType::Full => self.logger.err(&req, &handled, err.as_ref()),
-                };
-                res = Ok(handled);
+                self.logger.log(req, &res);
+                return Some(res);
             }
-            // Response is Ok
-            let mut res = res.unwrap();
+            Err(err) => err,
+        };
+
+        // IO error
+        if let HttpErrorType::Terminate = err.error_type() { return None; }
 
-            // Add our server name
-            if !self.name.is_empty() {
-                res.add_header("Server", &self.name);
+        // Mappers take precedence and keep their status code
+        for mapper in &self.error_mappers {
+            if let Some(mapped) = mapper.map(req, err.as_ref()) {
+                self.logger.err(req, &mapped, err.as_ref());
+                return Some(mapped);
             }
+        }
 
-            // Stop pipelining if:
-            // - service didn't consume the body completely
-            // - HTTP/1.0 (doesn't support pipelining)
-            if body.conn.limit() != 0 || req.version.is(1, 0) {
+        // Response is Err, should be handled with defined error handler
+        let mut handled = match err.error_type() {
+            HttpErrorType::Terminate => unreachable!(),
+            // Status code
+            HttpErrorType::Status => self.error_handler.plain_code(err.status_code()),
+            // Error with description
+            HttpErrorType::Full => self.error_handler.error(req, err.as_ref()),
+        };
+        // Always use the original status code in the error response (connection handler sets this)
+        handled.code = err.status_code();
+        handled.headers.extend(err.headers());
+        // Log the error
+        match err.error_type() {
+            HttpErrorType::Terminate => unreachable!(),
+            HttpErrorType::Status => self.logger.log(req, &handled),
+            HttpErrorType::Full => self.logger.err(req, &handled, err.as_ref()),
+        };
+        Some(handled)
+    }
+
+    /// Adds the server headers, returns `true` if connection has to be closed after this response
+    fn finalize(&self, req: &HttpRequest, res: &mut HttpResponse, body_consumed: bool) -> bool {
+        // Add our server name
+        if !self.name.is_empty() && !res.has_header("Server") {
+            res.add_header("Server", &self.name);
+        }
+        for header in &self.default_headers {
+            if !res.has_header(&header.name) {
+                res.headers.push(header.clone());
+            }
+        }
+
+        // Upgraded connection is never reused, and has its own `Connection` header
+        if let HttpBody::Upgrade(_) = res.body {
+            return true;
+        }
+        // Service has asked to close it
+        if res.is_closing() {
+            return true;
+        }
+
+        // Stop pipelining if:
+        // - service didn't consume the body completely
+        // - HTTP/1.0 (doesn't support pipelining)
+        if !body_consumed || req.version.is(1, 0) {
+            res.add_header("Connection", "close");
+            return true;
+        } else if req.version.is(1, 1) {
+            if !req.has_header("Connection") || req.cmp_header("Connection", "keep-alive") {
+                res.add_header("Connection", "keep-alive");
+            } else {
                 res.add_header("Connection", "close");
-                connection_close = true;
-            } else if req.version.is(1, 1) {
-                if !req.has_header("Connection") || req.cmp_header("Connection", "keep-alive") {
-                    res.add_header("Connection", "keep-alive");
-                } else {
-                    res.add_header("Connection", "close");
-                    connection_close = true;
-                }
+                return true;
             }
+        }
+        false
+    }
 
-            // Now, send the response
-            h1::send(&req, &mut res, &mut conn).await?;
+    /// Sends the response, reporting it to the logger if the client has not received all of it
+    async fn send(&self, req: &HttpRequest, res: &mut HttpResponse, conn: &mut dyn HttpConnection) -> io::Result<()> {
+        let progress = self.progress.as_deref();
+        // Only files are big enough to be worth it, SSE is slow by design
+        let is_file = matches!(res.body, HttpBody::File { .. } | HttpBody::Seekable { .. });
+        let result = match self.min_response_rate {
+            Some(rate) if is_file => h1::send(req, res, &mut MinRate::new(&mut *conn, rate), progress, self.stream_buffer_size).await,
+            _ => h1::send(req, res, conn, progress, self.stream_buffer_size).await,
+        };
+        if let Err(err) = &result {
+            self.logger.aborted(req, res, err);
+            return result;
+        }
+        // Delivered, so its follow-up work can start. Panics stay in the spawned task
+        for task in res.background.drain(..) {
+            tokio::spawn(task);
+        }
+
+        // Connection belongs to the other protocol now
+        if let HttpBody::Upgrade(handler) = &mut res.body {
+            return handler.upgrade_raw(conn).await;
+        }
+        Ok(())
+    }
+
+    /// Serves a batch of bodyless pipelined requests concurrently
+    ///
+    /// Responses are still sent in the order of requests, each one as soon as it and the ones before it are ready.
+    /// Returns `true` if connection has to be closed
+    async fn handle_batch(&self, batch: Vec<HttpRequest>, conn: &mut impl HttpConnection, deadline: Option<Instant>) -> io::Result<bool> {
+        let futures = batch.into_iter().map(|req| Box::pin(async move {
+            let res = self.respond(&req, &mut tokio::io::empty()).await;
+            (req, res)
+        })).collect();
+
+        let mut responses = Ordered::new(futures);
+        while let Some((req, res)) = responses.next().await {
+            // IO error
+            let Some(mut res) = res else { return Ok(true) };
+            close_after_deadline(&mut res, deadline);
+            let mut connection_close = self.finalize(&req, &mut res, true);
+
+            self.send(&req, &mut res, conn).await?;
             if let HttpBody::Sse(_) = res.body {
                 connection_close = true;
             }
+            // Remaining responses are dropped, just as their requests would be
+            if connection_close { return Ok(true); }
+        }
+        Ok(false)
+    }
+}
 
-            // Check if there is nothing more to read
-            if conn.fill_buf().await?.is_empty() { connection_close = true; }
+/// Checks if the request leaves the connection as it is, so the ones after it can be read right away
+fn batchable(req: &HttpRequest) -> bool {
+    // Bytes after an upgrade belong to the other protocol
+    let upgrade = req.get_header("Upgrade").is_some_and(|value| !value.trim().is_empty());
+    req.len == 0 && !req.chunked && !upgrade
+}
+
+/// Size of serialized headers
+fn headers_size(res: &HttpResponse) -> usize {
+    // `: ` and `\r\n`
+    res.headers.iter().map(|h| h.name.len() + h.value.len() + 4).sum()
+}
+
+/// Response headers exceeded [`HttpServer::max_response_headers_size`]
+#[derive(Debug)]
+struct ResponseHeadersTooLarge;
+
+impl fmt::Display for ResponseHeadersTooLarge {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("response headers too large")
+    }
+}
+
+impl Error for ResponseHeadersTooLarge {}
+impl HttpError for ResponseHeadersTooLarge {
+    fn error_type(&self) -> HttpErrorType { HttpErrorType::Status }
+}
+
+/// Closes the connection after this response if it has outlived [`HttpServer::max_connection_duration`]
+fn close_after_deadline(res: &mut HttpResponse, deadline: Option<Instant>) {
+    // Upgraded connection is closed anyway, and its `Connection` header must stay
+    if deadline.is_some_and(|deadline| Instant::now() >= deadline) && !matches!(res.body, HttpBody::Upgrade(_)) {
+        res.close_connection();
+    }
+}
+
+/// Takes `Upgrade: h2c` (HTTP/2 over cleartext) out of the request, so it is served with HTTP/1.1
+///
+/// Server may ignore an upgrade, and the connection stays open as if it was never asked for.
+/// Services see a plain request. HTTP/2 support would answer `101 Switching Protocols` here
+fn ignore_h2c(req: &mut HttpRequest) {
+    if !req.wants_upgrade("h2c") { return; }
+
+    let without = |value: &str, tokens: &[&str]| -> String {
+        let kept: Vec<_> = value.split(',').map(str::trim)
+            .filter(|option| !option.is_empty() && !tokens.iter().any(|token| option.eq_ignore_ascii_case(token)))
+            .collect();
+        kept.join(", ")
+    };
+    // Other protocols may still be offered
+    let other_upgrades = req.headers.iter()
+        .filter(|h| h.name.eq_ignore_ascii_case("Upgrade"))
+        .any(|h| !without(&h.value, &["h2c"]).is_empty());
+    let connection_options: &[&str] = if other_upgrades { &["HTTP2-Settings"] } else { &["Upgrade", "HTTP2-Settings"] };
+
+    for header in &mut req.headers {
+        if header.name.eq_ignore_ascii_case("Upgrade") {
+            header.value = without(&header.value, &["h2c"]);
+        } else if header.name.eq_ignore_ascii_case("Connection") {
+            header.value = without(&header.value, connection_options);
         }
-        // Loop ended, we close the connection now
-        conn.shutdown().await
+    }
+    req.headers.retain(|h| {
+        let emptied = (h.name.eq_ignore_ascii_case("Upgrade") || h.name.eq_ignore_ascii_case("Connection")) && h.value.is_empty();
+        !emptied && !h.name.eq_ignore_ascii_case("HTTP2-Settings")
+    });
+}
+
+/// Decrements the connection counter when connection is done
+struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl ConnectionGuard {
+    /// Increments the counter, returns the guard and new count
+    fn new(counter: &Arc<AtomicUsize>) -> (ConnectionGuard, usize) {
+        let count = counter.fetch_add(1, Ordering::Relaxed) + 1;
+        (ConnectionGuard(Arc::clone(counter)), count)
     }
 }
 
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// What the listener knows about a connection, it is copied into each request
+#[derive(Debug, Clone)]
+pub(crate) struct ConnInfo {
+    pub addr: SocketAddr,
+    pub local_addr: SocketAddr,
+    pub secure: bool,
+    pub trusted_proxy: bool,
+    pub peer_certificates: Option<Arc<Vec<Vec<u8>>>>,
+}
+
+impl ConnInfo {
+    fn apply(&self, req: &mut HttpRequest) {
+        req.addr = self.addr.ip().to_canonical();
+        req.local_addr = self.local_addr;
+        req.secure = self.secure;
+        req.trusted_proxy = self.trusted_proxy;
+        req.peer_certificates = self.peer_certificates.clone();
+    }
+}
+
+/// Why [`serve_tcp`] has stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ServeOutcome {
+    /// Shutdown signal (Ctrl+C or the one given to [`serve_tcp_with_shutdown`]) was received,
+    /// and all connections have finished within [`HttpServer::drain_timeout`] (if set)
+    GracefulShutdown,
+    /// Shutdown signal was received, but some connections were still open after
+    /// [`HttpServer::drain_timeout`] and had to be aborted
+    ForcedShutdown { aborted: usize },
+}
+
 /// Starts handling connections on a given [`HttpServer`], without TLS
-pub async fn serve_tcp(addr: &str, server: impl Into<Arc<HttpServer>>) -> io::Result<()> {
+///
+/// Stops on Ctrl+C. If the handler can't be installed, it runs until the process is killed.
+/// Errors are only returned when the listener could not be set up,
+/// `accept()` failures are retried with [`HttpServer::accept_backoff`]
+pub async fn serve_tcp(addr: &str, server: impl Into<Arc<HttpServer>>) -> io::Result<ServeOutcome> {
+    serve_tcp_with_shutdown(addr, server, ctrl_c()).await
+}
+
+/// Same as [`serve_tcp`], but stops when `shutdown` completes instead of installing a Ctrl+C handler
+///
+/// Use it when embedding the server into an application which handles signals itself
+pub async fn serve_tcp_with_shutdown(addr: &str, server: impl Into<Arc<HttpServer>>, shutdown: impl Future<Output = ()>) -> io::Result<ServeOutcome> {
+    serve_listener(bind(addr)?, server.into(), shutdown, |server, conn, info| async move {
+        server.handle_connection(BufReader::new(conn), info).await
+    }).await
+}
+
+/// Address of connections from Unix domain sockets, used as both `addr` and `local_addr` of their requests
+///
+/// They have no IP address, and the peer is on the same host anyway
+pub const UNIX_SOCKET_ADDR: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0));
+
+/// Starts handling connections on a Unix domain socket, for a reverse proxy on the same host
+///
+/// Works like [`serve_tcp`], but `path` is a socket file. A stale one left by a previous run is removed
+/// before binding, and the socket file is removed on shutdown. If another server still listens on it,
+/// fails with `AddrInUse`.
+///
+/// Requests come from [`UNIX_SOCKET_ADDR`], so add `127.0.0.1` to [`HttpServer::trusted_proxies`]
+/// to trust forwarded headers of the proxy. [`HttpServer::ip_filter`] sees the same address
+#[cfg(unix)]
+pub async fn serve_unix(path: impl AsRef<Path>, server: impl Into<Arc<HttpServer>>) -> io::Result<ServeOutcome> {
+    serve_unix_with_shutdown(path, server, ctrl_c()).await
+}
+
+/// Same as [`serve_unix`], but stops when `shutdown` completes, see [`serve_tcp_with_shutdown`]
+#[cfg(unix)]
+pub async fn serve_unix_with_shutdown(path: impl AsRef<Path>, server: impl Into<Arc<HttpServer>>, shutdown: impl Future<Output = ()>) -> io::Result<ServeOutcome> {
+    let path = path.as_ref();
+    remove_stale_socket(path).await?;
+    let listener = UnixListener::bind(path)?;
+    let outcome = serve_listener(listener, server.into(), shutdown, |server, conn, info| async move {
+        server.handle_connection(BufReader::new(conn), info).await
+    }).await;
+    let _ = std::fs::remove_file(path);
+    outcome
+}
+
+/// Removes the socket file if nobody listens on it anymore
+#[cfg(unix)]
+async fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let Ok(metadata) = std::fs::symlink_metadata(path) else { return Ok(()) };
+    // Other files are not ours to remove, bind fails on them
+    if !metadata.file_type().is_socket() { return Ok(()); }
+    match UnixStream::connect(path).await {
+        Ok(_) => Err(io::Error::new(io::ErrorKind::AddrInUse, "another server listens on this socket")),
+        Err(_) => std::fs::remove_file(path),
+    }
+}
+
+/// Completes on Ctrl+C, or never if the handler can't be installed
+pub(crate) async fn ctrl_c() {
+    if tokio::signal::ctrl_c().await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Creates the listening socket, returns it with the address it was bound to
+pub(crate) fn bind(addr: &str) -> io::Result<(TcpListener, SocketAddr)> {
     let addr: SocketAddr = addr.parse().map_err(io::Error::other)?;
 
     let sock = match addr {
@@ -195,37 +843,129 @@ pub async fn serve_tcp(addr: &str, server: impl Into<Arc<HttpServer>>) -> io::Re
     sock.set_nodelay(true)?;
 
     sock.bind(addr)?;
+    Ok((sock.listen(128)?, addr))
+}
+
+/// Source of connections for [`serve_listener`]
+pub(crate) trait Listener: Sync {
+    type Conn;
+
+    /// Accepts a connection, returns it with the peer address and the address it came to
+    fn accept(&self) -> impl Future<Output = io::Result<(Self::Conn, SocketAddr, SocketAddr)>> + Send;
+
+    /// Applies socket options of the server to accepted connection
+    fn configure(&self, _server: &HttpServer, _conn: &Self::Conn) {}
+}
+
+/// Listening socket with the address it was bound to
+impl Listener for (TcpListener, SocketAddr) {
+    type Conn = TcpStream;
 
-    let tcp = sock.listen(128)?;
-    let server = server.into();
-    let mut err_shown = false;
+    async fn accept(&self) -> io::Result<(TcpStream, SocketAddr, SocketAddr)> {
+        let (conn, addr) = self.0.accept().await?;
+        // Tells which listener the connection came from
+        let local_addr = conn.local_addr().unwrap_or(self.1);
+        Ok((conn, addr, local_addr))
+    }
+
+    fn configure(&self, server: &HttpServer, conn: &TcpStream) {
+        set_sockopts(server, conn);
+    }
+}
+
+#[cfg(unix)]
+impl Listener for UnixListener {
+    type Conn = UnixStream;
+
+    async fn accept(&self) -> io::Result<(UnixStream, SocketAddr, SocketAddr)> {
+        let (conn, _) = UnixListener::accept(self).await?;
+        Ok((conn, UNIX_SOCKET_ADDR, UNIX_SOCKET_ADDR))
+    }
+}
+
+/// Accept loop shared by all listeners, `handle` serves one accepted connection
+pub(crate) async fn serve_listener<L, F, Fut>(listener: L, server: Arc<HttpServer>, shutdown: impl Future<Output = ()>, handle: F) -> io::Result<ServeOutcome>
+where
+    L: Listener,
+    F: Fn(Arc<HttpServer>, L::Conn, ConnInfo) -> Fut,
+    Fut: Future<Output = io::Result<()>> + Send + 'static,
+{
+    let mut backoff = Duration::ZERO;
+    let connections = Arc::new(AtomicUsize::new(0));
+    let mut tasks = JoinSet::new();
+    let mut shutdown = pin!(shutdown);
     loop {
         // This way, shutdown is handled gracefully
-        let result = Or::new(tcp.accept(), tokio::signal::ctrl_c()).await;
+        let result = Or::new(listener.accept(), shutdown.as_mut()).await;
         if result.is_err() { break; }
+        // Forget the finished connections
+        while tasks.try_join_next().is_some() {}
 
         match result.unwrap() {
-            Ok((conn, addr)) => {
-                err_shown = false;
+            Ok((conn, addr, local_addr)) => {
+                backoff = Duration::ZERO;
+                if !server.ip_filter.allows(addr.ip()) {
+                    // Not welcome here
+                    continue;
+                }
+                let (guard, count) = ConnectionGuard::new(&connections);
+                if server.max_connections.is_some_and(|max| count > max) {
+                    // Too many connections, drop this one
+                    continue;
+                }
+                listener.configure(&server, &conn);
+                let trusted_proxy = server.trusted_proxies.iter().any(|proxy| proxy.contains(addr.ip()));
+                let info = ConnInfo { addr, local_addr, secure: false, trusted_proxy, peer_certificates: None };
+                let connection = handle(Arc::clone(&server), conn, info);
                 let server2 = Arc::clone(&server);
-                tokio::spawn(async move {
+                tasks.spawn(async move {
+                    let _guard = guard;
                     // ignore network errors
-                    let _ = server2.handle_connection(BufReader::new(conn), addr).await;
+                    if let Err(err) = connection.await
+                        && !matches!(err.error_type(), HttpErrorType::Terminate)
+                    {
+                        server2.logger.conn_err(addr.ip().to_canonical(), &err);
+                    }
                 });
             }
             Err(e) => {
                 // this may fire when fd limit is exhausted
-                if !err_shown {
-                    println!("DrakoHTTP critical error: connection not accepted: {e}");
-                    err_shown = true;
-                }
-                let d = Duration::from_millis(100);
-                tokio::time::sleep(d).await;
+                server.logger.accept_err(&e);
+                backoff = (backoff * 2).max(server.accept_backoff).min(server.max_accept_backoff);
+                tokio::time::sleep(backoff).await;
             }
         };
     }
 
-    Ok(())
+    // Stop accepting, then let open connections finish
+    drop(listener);
+    while tasks.try_join_next().is_some() {}
+    server.logger.shutdown(tasks.len());
+    let Some(drain_timeout) = server.drain_timeout else {
+        tasks.detach_all();
+        return Ok(ServeOutcome::GracefulShutdown);
+    };
+    let drain = async { while tasks.join_next().await.is_some() {} };
+    if tokio::time::timeout(drain_timeout, drain).await.is_ok() {
+        return Ok(ServeOutcome::GracefulShutdown);
+    }
+    let aborted = tasks.len();
+    tasks.shutdown().await;
+    Ok(ServeOutcome::ForcedShutdown { aborted })
+}
+
+/// Applies socket options of accepted connection, failures are not fatal
+fn set_sockopts(server: &HttpServer, conn: &TcpStream) {
+    let sock = SockRef::from(conn);
+    if let Some(time) = server.tcp_keepalive {
+        let keepalive = TcpKeepalive::new().with_time(time);
+        #[cfg(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios", target_os = "freebsd", target_os = "netbsd", windows))]
+        let keepalive = keepalive.with_interval(time);
+        let _ = sock.set_tcp_keepalive(&keepalive);
+    }
+    if let Some(linger) = server.linger {
+        let _ = sock.set_linger(Some(linger));
+    }
 }
 
 /// Builds the tokio runtime
@@ -236,3 +976,587 @@ pub fn tokio_rt() -> io::Result<tokio::runtime::Runtime> {
         .enable_all()
         .build()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::duplex;
+    use crate::reqres::res;
+
+    /// Feeds raw requests to the server, returns everything it has sent back
+    fn run(server: HttpServer, input: &[u8]) -> String {
+        tokio_rt().unwrap().block_on(async {
+            let (mut client, conn) = duplex(65536);
+            client.write_all(input).await.unwrap();
+            client.shutdown().await.unwrap();
+            let addr = "127.0.0.1:8080".parse().unwrap();
+            let info = ConnInfo { addr, local_addr: addr, secure: false, trusted_proxy: false, peer_certificates: None };
+            server.handle_connection(BufReader::new(conn), info).await.unwrap();
+            let mut out = String::new();
+            client.read_to_string(&mut out).await.unwrap();
+            out
+        })
+    }
+
+    struct NotModified;
+    impl HttpService for NotModified {
+        async fn request(&self, _route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            let mut res = res::text("stale body");
+            res.code = StatusCode::NOT_MODIFIED;
+            Ok(res)
+        }
+    }
+
+    #[test]
+    fn not_modified_keep_alive() {
+        let mut server = HttpServer::new();
+        server.service(NotModified);
+        let out = run(server, b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+        assert_eq!(out.matches("HTTP/1.1 304 Not modified\r\n").count(), 2);
+        assert!(out.contains("Connection: keep-alive\r\n"));
+        assert!(!out.contains("Content-Length"));
+        assert!(!out.contains("stale body"));
+    }
+
+    struct Closing;
+    impl HttpService for Closing {
+        async fn request(&self, _route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            let mut res = res::text("bye");
+            res.close_connection();
+            Ok(res)
+        }
+    }
+
+    #[test]
+    fn close_connection() {
+        let mut server = HttpServer::new();
+        server.service(Closing);
+        let out = run(server, b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+        assert_eq!(out.matches("HTTP/1.1 200 OK\r\n").count(), 1);
+        assert_eq!(out.matches("Connection: close\r\n").count(), 1);
+        assert!(!out.contains("keep-alive"));
+    }
+
+    #[test]
+    fn shutdown_future() {
+        let outcome = tokio_rt().unwrap().block_on(serve_tcp_with_shutdown("127.0.0.1:0", HttpServer::new(), async {}));
+        assert_eq!(outcome.unwrap(), ServeOutcome::GracefulShutdown);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket() {
+        let path = std::env::temp_dir().join(format!("dhttp-{}.sock", std::process::id()));
+        // Left behind by a crashed server
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+        let rt = tokio_rt().unwrap();
+        let server = rt.spawn(serve_unix_with_shutdown(path.clone(), HttpServer::new(), tokio::time::sleep(Duration::from_millis(200))));
+        let out = rt.block_on(async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let mut conn = UnixStream::connect(&path).await.unwrap();
+            conn.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").await.unwrap();
+            let mut out = String::new();
+            conn.read_to_string(&mut out).await.unwrap();
+            out
+        });
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(rt.block_on(server).unwrap().unwrap(), ServeOutcome::GracefulShutdown);
+        assert!(!path.exists());
+    }
+
+    struct EarlyHints;
+    impl HttpService for EarlyHints {
+        async fn request(&self, _route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            let mut res = res::text("page");
+            res.early_hint("</a.css>; rel=preload").early_hint("</b.js>; rel=preload");
+            Ok(res)
+        }
+    }
+
+    #[test]
+    fn early_hints() {
+        let mut server = HttpServer::new();
+        server.service(EarlyHints);
+        let out = run(server, b"GET / HTTP/1.1\r\n\r\n");
+        assert!(out.starts_with("HTTP/1.1 103 Early hints\r\nLink: </a.css>; rel=preload\r\nLink: </b.js>; rel=preload\r\n\r\nHTTP/1.1 200 OK\r\n"));
+        assert!(out.ends_with("\r\n\r\npage"));
+
+        let mut server = HttpServer::new();
+        server.service(EarlyHints);
+        let out = run(server, b"GET / HTTP/1.0\r\n\r\n");
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[test]
+    fn max_connection_duration() {
+        let mut server = HttpServer::new();
+        server.service(NotModified).max_connection_duration(Duration::ZERO);
+        let out = run(server, b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n");
+        assert_eq!(out.matches("HTTP/1.1 304 Not modified\r\n").count(), 1);
+        assert!(out.contains("Connection: close\r\n"));
+    }
+
+    struct StreamLen;
+    impl HttpService for StreamLen {
+        async fn request(&self, _route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            Ok(res::stream_with_len("text/plain", 5, &b"hello"[..]))
+        }
+    }
+
+    #[test]
+    fn stream_head_length() {
+        let mut server = HttpServer::new();
+        server.service(StreamLen);
+        let out = run(server, b"HEAD / HTTP/1.1\r\n\r\n");
+        assert!(out.contains("Content-Length: 5\r\n"));
+        assert!(!out.contains("Transfer-Encoding"));
+
+        let mut server = HttpServer::new();
+        server.service(StreamLen);
+        let out = run(server, b"GET / HTTP/1.1\r\n\r\n");
+        assert!(!out.contains("Content-Length"));
+        assert!(out.contains("Transfer-Encoding: chunked\r\n"));
+    }
+
+    struct Upload;
+    impl HttpService for Upload {
+        async fn request(&self, _route: &str, _req: &HttpRequest, body: &mut dyn HttpRead) -> HttpResult {
+            let mut data = String::new();
+            body.read_to_string(&mut data).await?;
+            Ok(res::text(data))
+        }
+
+        // Accepts a body, and GET for the pipelined request after it
+        fn filter(&self, _route: &str, req: &HttpRequest) -> HttpResult<()> {
+            if !matches!(req.method, HttpMethod::Get | HttpMethod::Post) { return Err(StatusCode::METHOD_NOT_ALLOWED.into()); }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn chunked_request() {
+        let mut server = HttpServer::new();
+        server.service(Upload);
+        let req = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;name=x\r\nhello\r\nA\r\n, chunked!\r\n0\r\nTrailer: 1\r\n\r\nGET / HTTP/1.1\r\n\r\n";
+        let out = run(server, req);
+        assert!(out.contains("Content-Length: 15\r\n"));
+        assert!(out.contains("hello, chunked!"));
+        assert_eq!(out.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+
+        let mut server = HttpServer::new();
+        server.service(Upload);
+        let out = run(server, b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\nhello\r\n0\r\n\r\n");
+        assert!(out.starts_with("HTTP/1.1 400 Bad request\r\n"));
+
+        let mut server = HttpServer::new();
+        server.service(Upload);
+        let out = run(server, b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n");
+        assert!(out.starts_with("HTTP/1.1 501 Not implemented\r\n"));
+
+        let mut server = HttpServer::new();
+        server.service(Upload);
+        let out = run(server, b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 5\r\n\r\nhello");
+        assert!(out.starts_with("HTTP/1.1 400 Bad request\r\n"));
+
+        // Over the body limit, in total or in one chunk
+        for (max_chunk, input) in [(16, &b"5\r\nhello\r\n6\r\nhello!\r\n0\r\n\r\n"[..]), (4, b"5\r\nhello\r\n0\r\n\r\n")] {
+            let mut server = HttpServer::new();
+            server.service(Upload).max_body_size(10).max_chunk_size(max_chunk);
+            let mut req = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+            req.extend_from_slice(input);
+            let out = run(server, &req);
+            assert!(out.starts_with("HTTP/1.1 413 Request entity too large\r\n"));
+        }
+    }
+
+    struct Digest;
+    impl HttpService for Digest {
+        async fn request(&self, _route: &str, req: &HttpRequest, body: &mut dyn HttpRead) -> HttpResult {
+            // Not there before the body is read
+            assert!(req.trailers().is_none());
+            body.read_to_end(&mut vec![]).await?;
+            Ok(res::text(format!("{:?}", req.trailers())))
+        }
+
+        fn filter(&self, _route: &str, _req: &HttpRequest) -> HttpResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn chunked_trailers() {
+        let req = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\nContent-Digest: sha=:x:\r\nX-Done:yes\r\n\r\n";
+        let mut server = HttpServer::new();
+        server.service(Digest).capture_trailers(true);
+        assert!(run(server, req).contains("Some([Content-Digest: sha=:x:, X-Done: yes])"));
+
+        let mut server = HttpServer::new();
+        server.service(Digest);
+        assert!(run(server, req).ends_with("None"));
+    }
+
+    struct German;
+    impl HttpService for German {
+        async fn request(&self, _route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            let mut res = res::text("hallo");
+            res.add_header("content-language", "de");
+            Ok(res)
+        }
+    }
+
+    #[test]
+    fn default_headers() {
+        let mut server = HttpServer::new();
+        server.default_header("X-Frame-Options", "DENY").default_header("Content-Language", "en");
+        server.service(German);
+        let out = run(server, b"GET / HTTP/1.1\r\n\r\n");
+        assert!(out.contains("Server: DrakoHTTP\r\n"));
+        assert!(out.contains("X-Frame-Options: DENY\r\n"));
+        assert!(out.contains("content-language: de\r\n"));
+        assert!(!out.contains("Content-Language: en"));
+    }
+
+    struct StaleLength;
+    impl HttpService for StaleLength {
+        async fn request(&self, _route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            // as if the body was compressed after setting it
+            let mut res = res::text("short");
+            res.add_header("Content-Length", "999");
+            Ok(res)
+        }
+    }
+
+    #[test]
+    fn stale_content_length() {
+        let mut server = HttpServer::new();
+        server.service(StaleLength);
+        let out = run(server, b"GET / HTTP/1.1\r\n\r\nHEAD / HTTP/1.1\r\n\r\n");
+        assert_eq!(out.matches("Content-Length: 5\r\n").count(), 2);
+        assert!(!out.contains("999"));
+    }
+
+    #[test]
+    fn options_asterisk() {
+        let mut server = HttpServer::new();
+        server.service(NotModified).server_methods(vec![HttpMethod::Get, HttpMethod::Head]);
+        let out = run(server, b"OPTIONS * HTTP/1.1\r\n\r\nGET * HTTP/1.1\r\n\r\n");
+        assert!(out.starts_with("HTTP/1.1 204 No content\r\n"));
+        assert!(out.contains("Allow: GET, HEAD\r\n"));
+        assert!(out.contains("HTTP/1.1 400 Bad request\r\n"));
+    }
+
+    /// Tells which upgrade it was asked for
+    struct Upgrades;
+    impl HttpService for Upgrades {
+        async fn request(&self, _route: &str, req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            Ok(res::text(format!("upgrade: {}", req.get_header("Upgrade").unwrap_or("none"))))
+        }
+    }
+
+    #[test]
+    fn h2c_upgrade_ignored() {
+        let mut server = HttpServer::new();
+        server.service(Upgrades);
+        let h2c = b"GET / HTTP/1.1\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABkAARAAAAAAAIAAAAA\r\n\r\n";
+        let mut input = h2c.to_vec();
+        input.extend_from_slice(b"GET / HTTP/1.1\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c, websocket\r\n\r\n");
+        let out = run(server, &input);
+        // Answered with HTTP/1.1, and the connection is kept for the next request
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(!out.contains("HTTP/1.1 101"));
+        assert!(out.contains("upgrade: none"));
+        assert!(out.contains("upgrade: websocket"));
+    }
+
+    #[test]
+    fn h2_prior_knowledge() {
+        let out = run(HttpServer::new(), b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n");
+        assert!(out.starts_with("HTTP/1.1 505 "));
+        assert_eq!(out.matches("HTTP/1.1").count(), 1);
+    }
+
+    #[test]
+    fn read_timeouts() {
+        tokio_rt().unwrap().block_on(async {
+            let mut server = HttpServer::new();
+            server.header_read_timeout(Duration::from_millis(50)).keep_alive_timeout(Duration::from_millis(50));
+            let addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+            let info = ConnInfo { addr, local_addr: addr, secure: false, trusted_proxy: false, peer_certificates: None };
+
+            // Client never finishes its headers
+            let (mut client, conn) = duplex(65536);
+            client.write_all(b"GET / HTTP/1.1\r\nHost: a\r\n").await.unwrap();
+            server.handle_connection(BufReader::new(conn), info.clone()).await.unwrap();
+            let mut out = String::new();
+            client.read_to_string(&mut out).await.unwrap();
+            assert!(out.starts_with("HTTP/1.1 408 Request timeout\r\n"));
+
+            // Client keeps the connection open after its request
+            let (mut client, conn) = duplex(65536);
+            client.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+            server.handle_connection(BufReader::new(conn), info).await.unwrap();
+            let mut out = String::new();
+            client.read_to_string(&mut out).await.unwrap();
+            assert!(out.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(!out.contains("408"));
+        });
+    }
+
+    static BACKGROUND_RAN: AtomicUsize = AtomicUsize::new(0);
+
+    /// Schedules a task after the response, it panics on `/panic`
+    struct Background;
+    impl HttpService for Background {
+        async fn request(&self, route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            let mut res = res::text("done");
+            let panics = route == "/panic";
+            res.after_response(async move {
+                if panics { panic!("background task failed"); }
+                BACKGROUND_RAN.fetch_add(1, Ordering::Relaxed);
+            });
+            Ok(res)
+        }
+    }
+
+    #[test]
+    fn background_tasks() {
+        let mut server = HttpServer::new();
+        server.service(Background);
+        tokio_rt().unwrap().block_on(async {
+            let (mut client, conn) = duplex(65536);
+            client.write_all(b"GET /panic HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n").await.unwrap();
+            client.shutdown().await.unwrap();
+            let addr = "127.0.0.1:8080".parse().unwrap();
+            let info = ConnInfo { addr, local_addr: addr, secure: false, trusted_proxy: false, peer_certificates: None };
+            server.handle_connection(BufReader::new(conn), info).await.unwrap();
+            let mut out = String::new();
+            client.read_to_string(&mut out).await.unwrap();
+            assert_eq!(out.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            assert_eq!(BACKGROUND_RAN.load(Ordering::Relaxed), 1);
+        });
+    }
+
+    #[test]
+    fn pipelined_partial_head() {
+        tokio_rt().unwrap().block_on(async {
+            let server = HttpServer::new();
+            let (mut client, conn) = duplex(65536);
+            let addr = "127.0.0.1:8080".parse().unwrap();
+            let info = ConnInfo { addr, local_addr: addr, secure: false, trusted_proxy: false, peer_certificates: None };
+            let handle = tokio::spawn(async move { server.handle_connection(BufReader::new(conn), info).await });
+
+            // Second head is not complete, first response is sent anyway
+            client.write_all(b"GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\nHost: a\r\n").await.unwrap();
+            let mut buf = [0; 1024];
+            let read = tokio::time::timeout(Duration::from_secs(1), client.read(&mut buf)).await;
+            let len = read.expect("first response is held back").unwrap();
+
+            client.write_all(b"\r\n").await.unwrap();
+            client.shutdown().await.unwrap();
+            handle.await.unwrap().unwrap();
+            let mut out = String::from_utf8_lossy(&buf[..len]).into_owned();
+            client.read_to_string(&mut out).await.unwrap();
+            assert_eq!(out.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+        });
+    }
+
+    /// Answers `/slow` after a while, the rest right away
+    struct Slow;
+    impl HttpService for Slow {
+        async fn request(&self, route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            if route == "/slow" { tokio::time::sleep(Duration::from_millis(500)).await; }
+            Ok(res::text(route.to_string()))
+        }
+    }
+
+    #[test]
+    fn pipelined_responses_not_held_back() {
+        tokio_rt().unwrap().block_on(async {
+            let mut server = HttpServer::new();
+            server.service(Slow);
+            let (mut client, conn) = duplex(65536);
+            let addr = "127.0.0.1:8080".parse().unwrap();
+            let info = ConnInfo { addr, local_addr: addr, secure: false, trusted_proxy: false, peer_certificates: None };
+            let handle = tokio::spawn(async move { server.handle_connection(BufReader::new(conn), info).await });
+
+            // First response doesn't wait for the slow one after it
+            client.write_all(b"GET /fast HTTP/1.1\r\n\r\nGET /slow HTTP/1.1\r\n\r\n").await.unwrap();
+            let mut buf = [0; 1024];
+            let read = tokio::time::timeout(Duration::from_millis(250), client.read(&mut buf)).await;
+            let len = read.expect("first response is held back").unwrap();
+            assert!(String::from_utf8_lossy(&buf[..len]).ends_with("/fast"));
+
+            client.shutdown().await.unwrap();
+            handle.await.unwrap().unwrap();
+            let mut out = String::new();
+            client.read_to_string(&mut out).await.unwrap();
+            assert!(out.ends_with("/slow"));
+        });
+    }
+
+    /// Sends back what it got after the upgrade
+    struct Replay;
+    impl crate::reqres::upgrade::HttpUpgrade for Replay {
+        async fn upgrade(&mut self, conn: &mut dyn HttpConnection) -> io::Result<()> {
+            let mut data = vec![];
+            conn.read_to_end(&mut data).await?;
+            conn.write_all(b"got: ").await?;
+            conn.write_all(&data).await
+        }
+    }
+
+    struct ReplayService;
+    impl HttpService for ReplayService {
+        async fn request(&self, _route: &str, req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            if !req.wants_upgrade("replay") { return Ok(res::text("plain")); }
+            Ok(res::upgrade("replay", Replay))
+        }
+    }
+
+    #[test]
+    fn upgrade_not_batched() {
+        let mut server = HttpServer::new();
+        server.service(ReplayService);
+        let out = run(server, b"GET / HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: replay\r\n\r\nGET /next HTTP/1.1\r\n\r\n");
+        assert!(out.starts_with("HTTP/1.1 101 "));
+        assert!(out.ends_with("got: GET /next HTTP/1.1\r\n\r\n"));
+        assert!(!out.contains("plain"));
+    }
+
+    #[test]
+    fn method_routing() {
+        let mut router = crate::service::Router::new();
+        // Upload has its own filter, the default one would refuse POST
+        router.get("/a", NotModified).post("/a", Upload);
+        let mut server = HttpServer::new();
+        server.service(router);
+        let out = run(server, b"HEAD /a HTTP/1.1\r\n\r\nDELETE /a HTTP/1.1\r\n\r\nPOST /a HTTP/1.1\r\nContent-Length: 3\r\n\r\nbye");
+        assert!(out.starts_with("HTTP/1.1 304 Not modified\r\n"));
+        assert!(out.contains("HTTP/1.1 405 Method not allowed\r\n"));
+        assert!(out.contains("Allow: GET, POST, HEAD\r\n"));
+        assert!(out.ends_with("bye"));
+    }
+
+    struct RequireKey;
+    impl crate::core::HttpMiddleware for RequireKey {
+        async fn handle(&self, route: &str, req: &HttpRequest, body: &mut dyn HttpRead, next: crate::core::Next<'_>) -> HttpResult {
+            if !req.has_header("X-Key") { return Err(StatusCode::FORBIDDEN.into()); }
+            let mut res = next.run(route, req, body).await?;
+            res.add_header("X-Checked", "1");
+            Ok(res)
+        }
+    }
+
+    #[test]
+    fn middleware() {
+        let mut router = crate::service::Router::new();
+        router.add("/a", NotModified.wrap(RequireKey));
+        let mut server = HttpServer::new();
+        server.service(router);
+        let out = run(server, b"GET /a HTTP/1.1\r\n\r\nGET /a HTTP/1.1\r\nX-Key: 1\r\n\r\n");
+        assert!(out.starts_with("HTTP/1.1 403 Forbidden\r\n"));
+        assert!(out.contains("HTTP/1.1 304 Not modified\r\n"));
+        assert!(out.contains("X-Checked: 1\r\n"));
+    }
+
+    #[test]
+    fn route_normalization() {
+        let mut router = crate::service::Router::new();
+        router.add("/a/b", NotModified);
+        let mut server = HttpServer::new();
+        server.service(router);
+        let out = run(server, b"GET /a//b HTTP/1.1\r\n\r\nGET /./a/./b HTTP/1.1\r\n\r\nGET /a/../a/b HTTP/1.1\r\n\r\n");
+        assert_eq!(out.matches("HTTP/1.1 304 Not modified\r\n").count(), 2);
+        assert!(out.contains("HTTP/1.1 400 Bad request\r\n"));
+    }
+
+    struct HugeHeaders;
+    impl HttpService for HugeHeaders {
+        async fn request(&self, _route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            let mut res = res::text("ok");
+            for _ in 0..100 { res.add_header("X-Loop", "0123456789"); }
+            Ok(res)
+        }
+    }
+
+    #[test]
+    fn max_response_headers_size() {
+        let mut server = HttpServer::new();
+        server.service(HugeHeaders);
+        server.max_response_headers_size = 1000;
+        let out = run(server, b"GET / HTTP/1.1\r\n\r\n");
+        assert!(out.starts_with("HTTP/1.1 500 Internal server error\r\n"));
+        assert!(!out.contains("X-Loop"));
+    }
+
+    struct Chunks;
+    impl HttpService for Chunks {
+        async fn request(&self, _route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            Ok(res::stream("text/plain", (&b"ab"[..]).chain(&b"cd"[..])))
+        }
+    }
+
+    #[test]
+    fn stream_coalesces_ready_reads() {
+        let mut server = HttpServer::new();
+        server.service(Chunks);
+        let out = run(server, b"GET / HTTP/1.1\r\n\r\n");
+        assert!(out.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(out.ends_with("\r\n\r\n4\r\nabcd\r\n0\r\n\r\n"));
+    }
+
+    struct Accepting;
+    impl HttpService for Accepting {
+        async fn request(&self, _route: &str, _req: &HttpRequest, body: &mut dyn HttpRead) -> HttpResult {
+            crate::core::connection::accept_body(body).await?;
+            let mut data = String::new();
+            body.read_to_string(&mut data).await?;
+            Ok(res::text(data))
+        }
+
+        fn filter(&self, _route: &str, _req: &HttpRequest) -> HttpResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn chunked_explicit_continue() {
+        tokio_rt().unwrap().block_on(async {
+            let mut server = HttpServer::new();
+            server.service(Accepting).implicit_continue(false);
+            let (mut client, conn) = duplex(65536);
+            let addr = "127.0.0.1:8080".parse().unwrap();
+            let info = ConnInfo { addr, local_addr: addr, secure: false, trusted_proxy: false, peer_certificates: None };
+            let handle = tokio::spawn(async move { server.handle_connection(BufReader::new(conn), info).await });
+
+            // Body is only sent after `100 Continue`
+            client.write_all(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nExpect: 100-continue\r\n\r\n").await.unwrap();
+            let mut buf = [0; 1024];
+            let read = tokio::time::timeout(Duration::from_secs(1), client.read(&mut buf)).await;
+            let len = read.expect("100 Continue is not sent").unwrap();
+            assert!(buf[..len].starts_with(b"HTTP/1.1 100 Continue\r\n\r\n"));
+
+            client.write_all(b"5\r\nhello\r\n0\r\n\r\n").await.unwrap();
+            client.shutdown().await.unwrap();
+            handle.await.unwrap().unwrap();
+            let mut out = String::from_utf8_lossy(&buf[..len]).into_owned();
+            client.read_to_string(&mut out).await.unwrap();
+            assert!(out.contains("HTTP/1.1 200 OK\r\n"));
+            assert!(out.ends_with("hello"));
+        });
+    }
+
+    #[test]
+    fn empty_body_no_continue() {
+        let mut server = HttpServer::new();
+        server.service(crate::service::Echo::new(1024));
+        let req = b"POST / HTTP/1.1\r\nContent-Length: 0\r\nExpect: 100-continue\r\n\r\n";
+        let out = run(server, &[&req[..], &req[..]].concat());
+        assert!(!out.contains("100 Continue"));
+        assert_eq!(out.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+        assert!(out.contains("Content-Length: 0\r\n"));
+    }
+}
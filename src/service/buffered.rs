@@ -0,0 +1,70 @@
+//! Body buffering service
+
+use crate::core::{HttpServiceRaw, HttpService, HttpResult, HttpRead};
+use crate::core::connection::read_to_bytes;
+use crate::reqres::{HttpRequest, StatusCode};
+
+/// Reads the whole request body into memory before calling the inner service
+///
+/// Services get a raw [`HttpRead`] by default, which streams the body as it arrives and suits large uploads.
+/// Behind `Buffered`, the body is already complete, so the inner service can read it with
+/// [`read_to_bytes`] or [`read_to_string`](crate::core::connection::read_to_string) without its own limit check.
+/// Bodies longer than `limit` fire `413 Request entity too large`: with `Content-Length`, before reading anything
+///
+/// ```
+/// # use dhttp::service::{Buffered, Echo};
+/// // Small forms, up to 64 KiB
+/// let service = Buffered::new(Echo::new(u64::MAX), 65536);
+/// ```
+pub struct Buffered {
+    service: Box<dyn HttpServiceRaw>,
+    limit: u64,
+}
+
+impl Buffered {
+    pub fn new(service: impl HttpServiceRaw, limit: u64) -> Buffered {
+        Buffered { service: Box::new(service), limit }
+    }
+}
+
+impl HttpService for Buffered {
+    async fn request(&self, route: &str, req: &HttpRequest, body: &mut dyn HttpRead) -> HttpResult {
+        let data = read_to_bytes(body, self.limit).await?;
+        self.service.request_raw(route, req, &mut &data[..]).await
+    }
+
+    fn filter(&self, route: &str, req: &HttpRequest) -> HttpResult<()> {
+        // Chunked body has no length yet, it is checked while reading
+        if req.len > self.limit { return Err(StatusCode::REQUEST_ENTITY_TOO_LARGE.into()); }
+        self.service.filter_raw(route, req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Buffered;
+    use crate::core::HttpService;
+    use crate::reqres::{HttpBody, HttpMethod, HttpRequest};
+    use crate::service::Echo;
+
+    #[test]
+    fn limit() {
+        let service = Buffered::new(Echo::new(u64::MAX), 4);
+        let mut req = HttpRequest { method: HttpMethod::Post, len: 2, ..Default::default() };
+        assert!(service.filter("/", &req).is_ok());
+        req.len = 5;
+        assert_eq!(service.filter("/", &req).unwrap_err().status_code().0, 413);
+
+        crate::server::tokio_rt().unwrap().block_on(async {
+            let res = service.request("/", &req, &mut &b"hi"[..]).await.unwrap();
+            let HttpBody::Bytes(body) = res.body else { panic!("not bytes") };
+            assert_eq!(body, b"hi");
+
+            // Chunked, length is not known in advance
+            req.len = 0;
+            req.chunked = true;
+            let err = service.request("/", &req, &mut &b"hello"[..]).await.unwrap_err();
+            assert_eq!(err.status_code().0, 413);
+        });
+    }
+}
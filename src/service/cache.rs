@@ -0,0 +1,398 @@
+//! Response cache service
+
+use std::collections::HashMap;
+use std::future::poll_fn;
+use std::sync::Mutex;
+use std::task::{Poll, Waker};
+use std::time::{Duration, Instant};
+
+use crate::core::{HttpServiceRaw, HttpService, HttpResult, HttpRead};
+use crate::reqres::{HttpRequest, HttpResponse, HttpHeader, HttpBody, HttpMethod, StatusCode};
+use crate::reqres::res::etag_matches;
+use crate::util::httpdate;
+
+/// Caches successful responses of the inner service in memory
+///
+/// Only `200 OK` responses to `GET` with an in-memory body are stored, keyed by route and the request
+/// headers listed in their `Vary`. They expire after `ttl`, or after `Cache-Control: max-age` if the response has it.
+/// Responses with `no-store`, `no-cache` or `private` are never stored.
+/// Neither are responses with `Set-Cookie` or to requests with `Authorization`, unless they are marked `public` or have `s-maxage`.
+/// `HEAD` requests are served from the cache too.
+/// If the client already has the cached response (`If-None-Match` matches its `ETag`, or it was not
+/// modified after `If-Modified-Since`), it gets `304 Not modified` without the body.
+/// Cached responses get an `Age` header with the seconds since they were stored, added to the `Age` of the inner service
+///
+/// When several requests miss the same route at once, only one of them runs the inner service, and the rest wait for its result.
+/// If its response was not stored for them (an error, `no-store`, or another `Vary` variant), they all run the inner service at once
+///
+/// ```
+/// # use std::time::Duration;
+/// # use dhttp::service::{Cache, DefaultService};
+/// let service = Cache::new(DefaultService, 100, Duration::from_secs(60));
+/// ```
+pub struct Cache {
+    service: Box<dyn HttpServiceRaw>,
+    capacity: usize,
+    ttl: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Stored responses by route, one per variant
+    entries: HashMap<String, Vec<Entry>>,
+    /// Routes being generated right now, with the requests waiting for them
+    in_flight: HashMap<String, Vec<Waker>>,
+    /// Total number of entries
+    len: usize,
+    /// Incremented on each use, for LRU eviction
+    tick: u64,
+}
+
+struct Entry {
+    /// Values of the request headers listed in `Vary`
+    vary: Vec<(String, Option<String>)>,
+    code: StatusCode,
+    headers: Vec<HttpHeader>,
+    content_type: String,
+    body: Vec<u8>,
+    /// `Age` of the response when it was stored
+    age: u64,
+    stored: Instant,
+    expires: Instant,
+    used: u64,
+}
+
+impl Entry {
+    fn matches(&self, req: &HttpRequest) -> bool {
+        self.vary.iter().all(|(name, value)| req.get_header(name) == value.as_deref())
+    }
+}
+
+impl Cache {
+    /// Wraps a service, storing up to `capacity` responses for `ttl`
+    pub fn new(service: impl HttpServiceRaw, capacity: usize, ttl: Duration) -> Cache {
+        Cache { service: Box::new(service), capacity, ttl, state: Mutex::default() }
+    }
+
+    /// Finds a fresh response for this request
+    fn get(&self, route: &str, req: &HttpRequest) -> Option<HttpResponse> {
+        let mut state = self.state.lock().unwrap();
+        state.tick += 1;
+        let tick = state.tick;
+        let now = Instant::now();
+        let entry = state.entries.get_mut(route)?
+            .iter_mut()
+            .find(|entry| entry.expires > now && entry.matches(req))?;
+        entry.used = tick;
+        let mut res = HttpResponse {
+            code: entry.code,
+            headers: entry.headers.clone(),
+            body: HttpBody::Bytes(entry.body.clone()),
+            content_type: entry.content_type.clone(),
+            informational: vec![],
+            background: vec![],
+        };
+        let age = entry.age + (now - entry.stored).as_secs();
+        res.add_header("Age", age.to_string());
+        Some(res)
+    }
+
+    /// Stores the response if it is cacheable
+    fn store(&self, route: &str, req: &HttpRequest, res: &HttpResponse) {
+        // Only in-memory bodies, streams may never end
+        let HttpBody::Bytes(body) = &res.body else { return };
+        if res.code.0 != 200 || self.capacity == 0 { return; }
+
+        let mut ttl = self.ttl;
+        let mut shared = false;
+        let cache_control = res.get_header("Cache-Control").unwrap_or_default();
+        for directive in cache_control.split(',').map(str::trim) {
+            if ["no-store", "no-cache", "private"].iter().any(|d| directive.eq_ignore_ascii_case(d)) {
+                return;
+            }
+            let name = directive.split_once('=').map_or(directive, |(name, _)| name);
+            if name.eq_ignore_ascii_case("public") || name.eq_ignore_ascii_case("s-maxage") {
+                shared = true;
+            }
+            if let Some((name, secs)) = directive.split_once('=') && name.eq_ignore_ascii_case("max-age") {
+                let Ok(secs) = secs.parse() else { return };
+                ttl = Duration::from_secs(secs);
+            }
+        }
+        if ttl.is_zero() { return; }
+        // Would be served to other users (RFC 9111 3.5)
+        if !shared && (req.has_header("Authorization") || res.has_header("Set-Cookie")) { return; }
+
+        let mut vary = vec![];
+        for name in res.get_header("Vary").unwrap_or_default().split(',').map(str::trim) {
+            // varies on something we can't see
+            if name == "*" { return; }
+            if !name.is_empty() {
+                vary.push((name.to_string(), req.get_header(name).map(str::to_string)));
+            }
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let state = &mut *state;
+        state.tick += 1;
+        let now = Instant::now();
+
+        // Drop expired entries and the variant being replaced
+        let mut removed = 0;
+        for entries in state.entries.values_mut() {
+            let len = entries.len();
+            entries.retain(|entry| entry.expires > now);
+            removed += len - entries.len();
+        }
+        if let Some(entries) = state.entries.get_mut(route) && let Some(i) = entries.iter().position(|entry| entry.matches(req)) {
+            entries.remove(i);
+            removed += 1;
+        }
+        state.entries.retain(|_, entries| !entries.is_empty());
+        state.len -= removed;
+
+        // Then evict the least recently used one
+        if state.len >= self.capacity {
+            let lru = state.entries.iter()
+                .flat_map(|(route, entries)| entries.iter().enumerate().map(move |(i, entry)| (entry.used, route, i)))
+                .min()
+                .map(|(_, route, i)| (route.clone(), i));
+            if let Some((route, i)) = lru && let Some(entries) = state.entries.get_mut(&route) {
+                entries.remove(i);
+                if entries.is_empty() { state.entries.remove(&route); }
+                state.len -= 1;
+            }
+        }
+
+        // Counted from now on, it is added back on each hit
+        let age = res.get_header("Age").and_then(|age| age.trim().parse().ok()).unwrap_or(0);
+        let headers = res.headers.iter().filter(|h| !h.name.eq_ignore_ascii_case("Age")).cloned().collect();
+        state.entries.entry(route.to_string()).or_default().push(Entry {
+            vary,
+            code: res.code,
+            headers,
+            content_type: res.content_type.clone(),
+            body: body.clone(),
+            age,
+            stored: now,
+            expires: now + ttl,
+            used: state.tick,
+        });
+        state.len += 1;
+    }
+
+    /// Waits until the route is not being generated anymore
+    async fn wait(&self, route: &str) {
+        poll_fn(|cx| {
+            let mut state = self.state.lock().unwrap();
+            match state.in_flight.get_mut(route) {
+                Some(wakers) => {
+                    if !wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                        wakers.push(cx.waker().clone());
+                    }
+                    Poll::Pending
+                }
+                None => Poll::Ready(()),
+            }
+        }).await
+    }
+}
+
+/// Checks the conditional headers of the request against validators of the cached response
+///
+/// `If-Modified-Since` is ignored when `If-None-Match` is present
+fn not_modified(req: &HttpRequest, res: &HttpResponse) -> bool {
+    if let Some(if_none_match) = req.get_header("If-None-Match") {
+        return res.get_header("ETag").is_some_and(|etag| etag_matches(if_none_match, etag));
+    }
+    let since = req.get_header("If-Modified-Since").and_then(httpdate::parse);
+    let modified = res.get_header("Last-Modified").and_then(httpdate::parse);
+    matches!((since, modified), (Some(since), Some(modified)) if modified <= since)
+}
+
+/// Wakes up the waiting requests when generation is done or cancelled
+struct InFlight<'a> {
+    cache: &'a Cache,
+    route: &'a str,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        let mut state = self.cache.state.lock().unwrap_or_else(|err| err.into_inner());
+        for waker in state.in_flight.remove(self.route).unwrap_or_default() {
+            waker.wake();
+        }
+    }
+}
+
+impl HttpService for Cache {
+    async fn request(&self, route: &str, req: &HttpRequest, body: &mut dyn HttpRead) -> HttpResult {
+        if req.method != HttpMethod::Get && req.method != HttpMethod::Head {
+            return self.service.request_raw(route, req, body).await;
+        }
+
+        let mut waited = false;
+        let leader = loop {
+            if let Some(mut res) = self.get(route, req) {
+                // Validators come from the variant picked by `Vary`
+                if not_modified(req, &res) {
+                    res.code = StatusCode::NOT_MODIFIED;
+                    res.body = HttpBody::Empty;
+                }
+                return Ok(res);
+            }
+            // HEAD responses are not stored, so there is nothing to wait for
+            if req.method == HttpMethod::Head {
+                return self.service.request_raw(route, req, body).await;
+            }
+
+            // Leader's response is not for us, waiting for another one would run the waiters one by one
+            if waited { break false; }
+
+            let generating = {
+                let mut state = self.state.lock().unwrap();
+                let generating = state.in_flight.contains_key(route);
+                if !generating { state.in_flight.insert(route.to_string(), vec![]); }
+                generating
+            };
+            if !generating { break true; }
+            // Leader has finished, its response may be cached now
+            self.wait(route).await;
+            waited = true;
+        };
+
+        let _in_flight = leader.then(|| InFlight { cache: self, route });
+        let res = self.service.request_raw(route, req, body).await?;
+        self.store(route, req, &res);
+        Ok(res)
+    }
+
+    fn filter(&self, route: &str, req: &HttpRequest) -> HttpResult<()> {
+        self.service.filter_raw(route, req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::Cache;
+    use crate::core::{HttpService, HttpResult, HttpRead};
+    use crate::reqres::{res, header, HttpRequest, StatusCode};
+    use crate::util::future::Ordered;
+
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Response with validators, counts how many times it was generated
+    struct Validated;
+    impl HttpService for Validated {
+        async fn request(&self, _route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            RUNS.fetch_add(1, Ordering::Relaxed);
+            let mut res = res::text("hello");
+            res.add_header("ETag", "W/\"v1\"").add_header("Last-Modified", "Sat, 03 Jan 2026 10:00:00 GMT");
+            Ok(res)
+        }
+    }
+
+    /// Response which already spent some time in another cache
+    struct Aged;
+    impl HttpService for Aged {
+        async fn request(&self, _route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            let mut res = res::text("hello");
+            res.add_header("Age", "5");
+            Ok(res)
+        }
+    }
+
+    #[test]
+    fn conditional() {
+        let cache = Cache::new(Validated, 10, Duration::from_secs(60));
+        let rt = crate::server::tokio_rt().unwrap();
+        let get = |name: &str, value: &str| {
+            let req = HttpRequest { headers: vec![header(name, value)], ..Default::default() };
+            rt.block_on(cache.request("/", &req, &mut &b""[..])).unwrap().code.0
+        };
+
+        // First one only fills the cache
+        assert_eq!(get("If-None-Match", "\"v1\""), 200);
+        assert_eq!(get("If-None-Match", "\"v0\", \"v1\""), 304);
+        assert_eq!(get("If-None-Match", "\"v2\""), 200);
+        assert_eq!(get("If-Modified-Since", "Sun, 04 Jan 2026 10:00:00 GMT"), 304);
+        assert_eq!(get("If-Modified-Since", "Fri, 02 Jan 2026 10:00:00 GMT"), 200);
+        assert_eq!(RUNS.load(Ordering::Relaxed), 1);
+    }
+
+    static RUNNING: AtomicUsize = AtomicUsize::new(0);
+    static MAX_RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+    /// Slow response that is never stored, remembers how many of them ran at once
+    struct Missing;
+    impl HttpService for Missing {
+        async fn request(&self, _route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            let running = RUNNING.fetch_add(1, Ordering::Relaxed) + 1;
+            MAX_RUNNING.fetch_max(running, Ordering::Relaxed);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            RUNNING.fetch_sub(1, Ordering::Relaxed);
+            Err(StatusCode::NOT_FOUND.into())
+        }
+    }
+
+    #[test]
+    fn uncacheable_waiters_run_together() {
+        let cache = Cache::new(Missing, 10, Duration::from_secs(60));
+        let req = HttpRequest::default();
+        let requests = (0..4).map(|_| Box::pin(async { cache.request("/", &req, &mut &b""[..]).await.is_err() })).collect();
+        let mut requests = Ordered::new(requests);
+        crate::server::tokio_rt().unwrap().block_on(async {
+            while let Some(err) = requests.next().await { assert!(err); }
+        });
+        // Leader runs alone, then all the waiters
+        assert_eq!(MAX_RUNNING.load(Ordering::Relaxed), 3);
+    }
+
+    static PERSONAL_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Sets a cookie except on `/plain`, `public` on `/public`
+    struct Personal;
+    impl HttpService for Personal {
+        async fn request(&self, route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            PERSONAL_RUNS.fetch_add(1, Ordering::Relaxed);
+            let mut res = res::text("hello");
+            if route != "/plain" { res.add_header("Set-Cookie", "session=1"); }
+            if route == "/public" { res.add_header("Cache-Control", "public"); }
+            Ok(res)
+        }
+    }
+
+    #[test]
+    fn personal_not_shared() {
+        let cache = Cache::new(Personal, 10, Duration::from_secs(60));
+        let rt = crate::server::tokio_rt().unwrap();
+        let runs = |route: &str, req: &HttpRequest| {
+            let before = PERSONAL_RUNS.load(Ordering::Relaxed);
+            for _ in 0..2 {
+                rt.block_on(cache.request(route, req, &mut &b""[..])).unwrap();
+            }
+            PERSONAL_RUNS.load(Ordering::Relaxed) - before
+        };
+        assert_eq!(runs("/", &HttpRequest::default()), 2);
+        assert_eq!(runs("/public", &HttpRequest::default()), 1);
+
+        let authorized = HttpRequest { headers: vec![header("Authorization", "Basic YTpi")], ..Default::default() };
+        assert_eq!(runs("/plain", &authorized), 2);
+        assert_eq!(runs("/plain", &HttpRequest::default()), 1);
+    }
+
+    #[test]
+    fn age() {
+        let cache = Cache::new(Aged, 10, Duration::from_secs(60));
+        let req = HttpRequest::default();
+        let rt = crate::server::tokio_rt().unwrap();
+        let miss = rt.block_on(cache.request("/", &req, &mut &b""[..])).unwrap();
+        assert_eq!(miss.get_all_headers("Age").count(), 1);
+        let hit = rt.block_on(cache.request("/", &req, &mut &b""[..])).unwrap();
+        assert_eq!(hit.get_all_headers("Age").collect::<Vec<_>>(), ["5"]);
+    }
+}
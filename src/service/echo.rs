@@ -0,0 +1,33 @@
+//! Echo service
+
+use crate::core::{HttpService, HttpResult, HttpRead};
+use crate::core::connection::read_to_bytes;
+use crate::reqres::{HttpRequest, HttpResponse, HttpMethod, StatusCode};
+
+/// Responds with the request body and its `Content-Type`
+///
+/// Accepts only `POST` and `PUT` requests with a body up to `max_size`
+pub struct Echo {
+    max_size: u64,
+}
+
+impl Echo {
+    pub fn new(max_size: u64) -> Echo {
+        Echo { max_size }
+    }
+}
+
+impl HttpService for Echo {
+    async fn request(&self, _route: &str, req: &HttpRequest, body: &mut dyn HttpRead) -> HttpResult {
+        // Chunked body has no length to check in the filter
+        let bytes = read_to_bytes(body, self.max_size).await?;
+        let content_type = req.get_header("Content-Type").unwrap_or("application/octet-stream");
+        Ok(HttpResponse::with_type(content_type, bytes))
+    }
+
+    fn filter(&self, _route: &str, req: &HttpRequest) -> HttpResult<()> {
+        if req.method != HttpMethod::Post && req.method != HttpMethod::Put { return Err(StatusCode::METHOD_NOT_ALLOWED.into()); }
+        if req.len > self.max_size { return Err(StatusCode::REQUEST_ENTITY_TOO_LARGE.into()); }
+        Ok(())
+    }
+}
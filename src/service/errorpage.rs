@@ -1,10 +1,16 @@
 use crate::core::{HttpError, HttpErrorHandler};
 use crate::reqres::{res, HttpRequest, HttpResponse, StatusCode};
 
-fn error_page(code: u16, code_desc: &str, desc: &str, name: &str) -> String {
+fn error_page(page: &ErrorPageHandler, code: u16, code_desc: &str, desc: &str) -> String {
+let ErrorPageHandler { name, light, dark, logo, head } = page;
+let (fg, bg, dark_fg, dark_bg) = (&light.text, &light.background, &dark.text, &dark.background);
+let logo = match logo {
+    Some(src) => format!(r#"<img src="{src}" alt="">"#),
+    None => String::new(),
+};
 format!(r#"<!doctype html>
-<html><title>{code} {code_desc}</title><meta name="viewport" content="width=device-width"><style>*{{font-family:sans-serif;color:#0e1219;border-color:#0e1219;background:#f9f9f9}}@media(prefers-color-scheme:dark){{*{{color:#47d8bb;border-color:#47d8bb;background:#0e1219}}}}h1{{margin:0;}}div{{position:fixed;top:50%;left:50%;transform:translate(-50%,-50%);padding:8px;border:4px solid}}</style><div>
-
+<html><title>{code} {code_desc}</title><meta name="viewport" content="width=device-width"><style>*{{font-family:sans-serif;color:{fg};border-color:{fg};background:{bg}}}@media(prefers-color-scheme:dark){{*{{color:{dark_fg};border-color:{dark_fg};background:{dark_bg}}}}}h1{{margin:0;}}div{{position:fixed;top:50%;left:50%;transform:translate(-50%,-50%);padding:8px;border:4px solid}}</style>{head}<div>
+{logo}
 <h1>    {code} {code_desc}    </h1>
         {desc}
 <hr><!--------------------------->
@@ -14,19 +20,57 @@ format!(r#"<!doctype html>
 "#)
 }
 
+/// Colors of the error page
+#[derive(Debug, Clone)]
+pub struct ErrorPageColors {
+    /// Text and border color
+    pub text: String,
+    pub background: String,
+}
+
 /// Default error handler, shows a nice error page
+///
+/// Page can be branded by changing its fields:
+/// ```
+/// # use dhttp::service::ErrorPageHandler;
+/// let mut handler = ErrorPageHandler::new("My server");
+/// handler.dark.text = "#ffb000".to_string();
+/// handler.logo = Some("/logo.svg".to_string());
+/// ```
 pub struct ErrorPageHandler {
+    /// Server name shown at the bottom
     pub name: String,
+    /// Colors for the light theme
+    pub light: ErrorPageColors,
+    /// Colors for the dark theme
+    pub dark: ErrorPageColors,
+    /// URL of an image shown above the error
+    pub logo: Option<String>,
+    /// Extra HTML inserted after the stylesheet, like a `<link>` to your own one
+    pub head: String,
+}
+
+impl ErrorPageHandler {
+    /// Error page with the default colors
+    pub fn new(name: impl Into<String>) -> ErrorPageHandler {
+        ErrorPageHandler {
+            name: name.into(),
+            light: ErrorPageColors { text: "#0e1219".to_string(), background: "#f9f9f9".to_string() },
+            dark: ErrorPageColors { text: "#47d8bb".to_string(), background: "#0e1219".to_string() },
+            logo: None,
+            head: String::new(),
+        }
+    }
 }
 
 impl HttpErrorHandler for ErrorPageHandler {
     fn error(&self, req: &HttpRequest, error: &dyn HttpError) -> HttpResponse {
         let code = error.status_code();
         let desc = error.http_description();
-        res::html(req, error_page(code.0, code.as_str(), &desc, &self.name))
+        res::html(req, error_page(self, code.0, code.as_str(), &desc))
     }
 
     fn plain_code(&self, code: StatusCode) -> HttpResponse {
-        res::html(&HttpRequest::default(), error_page(code.0, code.as_str(), "", &self.name))
+        res::html(&HttpRequest::default(), error_page(self, code.0, code.as_str(), ""))
     }
 }
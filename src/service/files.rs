@@ -1,34 +1,181 @@
-//! Files service
+//! Files services
 
-use std::path::PathBuf;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 
 use tokio::fs;
 
 use crate::core::{HttpService, HttpResult, HttpRead};
-use crate::reqres::{res, HttpRequest, StatusCode};
-use crate::util::path;
+use crate::reqres::{res, HttpRequest, HttpResponse, StatusCode};
+use crate::reqres::file::get_content_type;
+use crate::util::{path, qvalue};
+
+// Better formats first
+const IMAGE_FORMATS: &[(&str, &str)] = &[("avif", "image/avif"), ("webp", "image/webp")];
 
 /// Hosts a directory with static files
 pub struct Files {
     path: PathBuf,
+    negotiate_images: bool,
+    not_found_page: Option<PathBuf>,
 }
 
 impl Files {
     pub fn new(path: impl Into<PathBuf>) -> Files {
-        Files { path: path.into() }
+        Files { path: path.into(), negotiate_images: false, not_found_page: None }
+    }
+
+    /// Page served with `404 Not found` when a file doesn't exist, like `404.html`.
+    /// Relative path is resolved in the hosted directory
+    ///
+    /// Without it, the error handler renders the 404
+    pub fn not_found_page(&mut self, page: impl Into<PathBuf>) -> &mut Self {
+        self.not_found_page = Some(page.into());
+        self
+    }
+
+    /// Serves `photo.avif` or `photo.webp` in place of `photo.jpg` (or `.jpeg`, `.png`)
+    /// if it exists next to it and the client accepts it. URL stays the same
+    ///
+    /// Only exact types in `Accept` count, since browsers send `*/*` for images they can't show
+    pub fn negotiate_images(&mut self, enabled: bool) -> &mut Self {
+        self.negotiate_images = enabled;
+        self
+    }
+
+    /// Finds a better format of this image that the client accepts
+    async fn better_image(&self, req: &HttpRequest, path: &Path) -> Option<PathBuf> {
+        let accept = req.get_header("Accept")?;
+        let accepted = qvalue::parse(accept);
+        for (ext, mime) in IMAGE_FORMATS {
+            if !accepted.iter().any(|(ty, q)| ty.eq_ignore_ascii_case(mime) && *q > 0.0) { continue; }
+            let candidate = path.with_extension(ext);
+            if fs::metadata(&candidate).await.is_ok_and(|metadata| metadata.is_file()) {
+                return Some(candidate);
+            }
+        }
+        None
+    }
+
+    /// Responds with the [`not_found_page`](Files::not_found_page), keeping its content type
+    async fn not_found(&self) -> HttpResult {
+        let Some(page) = &self.not_found_page else { return Err(StatusCode::NOT_FOUND.into()) };
+        let page = self.path.join(page);
+        let body = fs::read(&page).await?;
+        let content_type = get_content_type(page.extension()).unwrap_or_default();
+        let mut res = HttpResponse::with_type(content_type, body);
+        res.code = StatusCode::NOT_FOUND;
+        Ok(res)
     }
 }
 
+/// Checks if this is an image that can have better formats
+fn is_negotiable_image(path: &Path) -> bool {
+    let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    ["jpg", "jpeg", "png"].iter().any(|e| ext.eq_ignore_ascii_case(e))
+}
+
 impl HttpService for Files {
     async fn request(&self, route: &str, req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
         let path = self.path.join(path::sanitize(route)?);
 
-        let metadata = fs::metadata(&path).await?;
+        let metadata = match fs::metadata(&path).await {
+            Err(err) if self.not_found_page.is_some() && matches!(err.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => {
+                return self.not_found().await;
+            }
+            metadata => metadata?,
+        };
 
         if metadata.is_dir() {
-            Err(StatusCode::NOT_FOUND.into())
+            self.not_found().await
+        } else if self.negotiate_images && is_negotiable_image(&path) {
+            let better = self.better_image(req, &path).await;
+            let mut res = res::file(req, better.as_deref().unwrap_or(&path)).await?;
+            // Caches have to store each format separately
+            res.add_header("Vary", "Accept");
+            Ok(res)
         } else {
             res::file(req, &path).await
         }
     }
 }
+
+/// Hosts a single file on any route it receives
+///
+/// Useful with exact routes in [`Router`](crate::service::Router), like `/favicon.ico`
+pub struct SingleFile {
+    path: PathBuf,
+}
+
+impl SingleFile {
+    pub fn new(path: impl Into<PathBuf>) -> SingleFile {
+        SingleFile { path: path.into() }
+    }
+}
+
+impl HttpService for SingleFile {
+    async fn request(&self, _route: &str, req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+        let metadata = fs::metadata(&self.path).await?;
+
+        if metadata.is_dir() {
+            Err(StatusCode::NOT_FOUND.into())
+        } else {
+            res::file(req, &self.path).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::Files;
+    use crate::core::HttpService;
+    use crate::reqres::{header, HttpBody, HttpRequest};
+
+    #[test]
+    fn negotiate_images() {
+        let dir = std::env::temp_dir().join(format!("dhttp-images-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("photo.jpg"), "jpeg").unwrap();
+        fs::write(dir.join("photo.webp"), "webp").unwrap();
+
+        let mut files = Files::new(&dir);
+        files.negotiate_images(true);
+        let rt = crate::server::tokio_rt().unwrap();
+        let get = |accept: &str| {
+            let req = HttpRequest { headers: vec![header("Accept", accept)], ..Default::default() };
+            rt.block_on(files.request("/photo.jpg", &req, &mut &b""[..])).unwrap()
+        };
+
+        let res = get("image/avif,image/webp,*/*");
+        assert_eq!(res.content_type, "image/webp");
+        assert_eq!(res.get_header("Vary"), Some("Accept"));
+        assert_eq!(get("image/webp;q=0, */*").content_type, "image/jpeg");
+        assert_eq!(get("*/*").content_type, "image/jpeg");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn not_found_page() {
+        let dir = std::env::temp_dir().join(format!("dhttp-404-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("404.html"), "<h1>missing</h1>").unwrap();
+
+        let mut files = Files::new(&dir);
+        let rt = crate::server::tokio_rt().unwrap();
+        let req = HttpRequest::default();
+        assert!(rt.block_on(files.request("/nope.txt", &req, &mut &b""[..])).is_err());
+
+        files.not_found_page("404.html");
+        for route in ["/nope.txt", "/sub", "/sub/nope/deeper"] {
+            let res = rt.block_on(files.request(route, &req, &mut &b""[..])).unwrap();
+            assert_eq!(res.code.0, 404);
+            assert_eq!(res.content_type, "text/html");
+            let HttpBody::Bytes(body) = res.body else { panic!("not bytes") };
+            assert_eq!(body, b"<h1>missing</h1>");
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,58 @@
+//! Service from a function
+
+use crate::core::{HttpService, HttpResult, HttpRead};
+use crate::reqres::HttpRequest;
+
+/// Function that can be used as a service, see [`service_fn`]
+///
+/// Implemented for `async fn(&str, &HttpRequest, &mut dyn HttpRead) -> HttpResult`
+pub trait ServiceFn<'a>: Send + Sync + 'static {
+    /// Future returned by this function
+    type Future: Future<Output = HttpResult> + Send + 'a;
+    /// Calls the function
+    fn call(&self, route: &'a str, req: &'a HttpRequest, body: &'a mut dyn HttpRead) -> Self::Future;
+}
+
+impl<'a, F, Fut> ServiceFn<'a> for F
+where
+    F: Fn(&'a str, &'a HttpRequest, &'a mut dyn HttpRead) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = HttpResult> + Send + 'a,
+{
+    type Future = Fut;
+
+    fn call(&self, route: &'a str, req: &'a HttpRequest, body: &'a mut dyn HttpRead) -> Fut {
+        self(route, req, body)
+    }
+}
+
+/// Service that calls a function, created by [`service_fn`]
+pub struct FnService<F> {
+    f: F,
+}
+
+/// Wraps an async function into a service with the default [`HttpService::filter`]
+///
+/// # Example
+/// ```
+/// # use dhttp::prelude::*;
+/// # use dhttp::reqres::res;
+/// use dhttp::service::service_fn;
+///
+/// async fn hello(_route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+///     Ok(res::text("Hello!\n"))
+/// }
+///
+/// let mut server = HttpServer::new();
+/// server.service(service_fn(hello));
+/// ```
+///
+/// Closures have trouble with borrowing their arguments in the returned future, so prefer `async fn`s
+pub fn service_fn<F: for<'a> ServiceFn<'a>>(f: F) -> FnService<F> {
+    FnService { f }
+}
+
+impl<F: for<'a> ServiceFn<'a>> HttpService for FnService<F> {
+    async fn request(&self, route: &str, req: &HttpRequest, body: &mut dyn HttpRead) -> HttpResult {
+        self.f.call(route, req, body).await
+    }
+}
@@ -0,0 +1,294 @@
+//! Response compression service
+
+use std::io::{self, Write};
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::core::{HttpServiceRaw, HttpService, HttpResult, HttpRead};
+use crate::reqres::{HttpRequest, HttpResponse, HttpBody};
+use crate::util::qvalue;
+
+/// Smaller bodies barely shrink, or even grow because of the gzip header
+const DEFAULT_MIN_SIZE: usize = 1024;
+
+/// Compresses responses of the inner service with gzip, if the client accepts it
+///
+/// Only bodies with a compressible `Content-Type` (text, JSON, XML, JavaScript, SVG, wasm) are compressed:
+/// - in-memory bodies, if they are at least [`min_size`](Gzip::min_size) long
+/// - streams, unless disabled with [`streams`](Gzip::streams). Streams of known length (`Content-Length`
+///   from the service) follow `min_size` too, the rest are always compressed
+///
+/// Files, SSE, partial responses and responses that already have `Content-Encoding` are left as is.
+/// A strong `ETag` becomes weak, since the bytes are not the same anymore
+///
+/// ```
+/// # use dhttp::service::{Gzip, DefaultService};
+/// let mut service = Gzip::new(DefaultService);
+/// service.min_size(4096).streams(false);
+/// ```
+pub struct Gzip {
+    service: Box<dyn HttpServiceRaw>,
+    min_size: usize,
+    streams: bool,
+}
+
+impl Gzip {
+    pub fn new(service: impl HttpServiceRaw) -> Gzip {
+        Gzip { service: Box::new(service), min_size: DEFAULT_MIN_SIZE, streams: true }
+    }
+
+    /// Bodies shorter than this are sent uncompressed, 1 KiB by default
+    pub fn min_size(&mut self, min_size: usize) -> &mut Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Whether streams are compressed, enabled by default
+    ///
+    /// Each chunk read from the stream is flushed right away, so it reaches the client without delay
+    pub fn streams(&mut self, streams: bool) -> &mut Self {
+        self.streams = streams;
+        self
+    }
+
+    fn compress(&self, res: &mut HttpResponse) -> io::Result<()> {
+        // Ranges refer to the uncompressed bytes
+        if res.code.0 == 206 || res.has_header("Content-Encoding") { return Ok(()); }
+
+        match &res.body {
+            HttpBody::Bytes(body) => {
+                if body.len() < self.min_size { return Ok(()); }
+                let mut encoder = GzEncoder::new(Vec::with_capacity(body.len() / 4), Compression::default());
+                encoder.write_all(body)?;
+                let compressed = encoder.finish()?;
+                if compressed.len() >= body.len() { return Ok(()); }
+                res.body = HttpBody::Bytes(compressed);
+            }
+            HttpBody::Stream(_) => {
+                let len = res.get_header("Content-Length").and_then(|len| len.parse::<u64>().ok());
+                if len.is_some_and(|len| len < self.min_size as u64) { return Ok(()); }
+                // It won't be the same anymore
+                res.headers.retain(|h| !h.name.eq_ignore_ascii_case("Content-Length"));
+                let HttpBody::Stream(stream) = mem::replace(&mut res.body, HttpBody::Empty) else { unreachable!() };
+                res.body = HttpBody::Stream(Box::new(GzipStream::new(stream)));
+            }
+            _ => return Ok(()),
+        }
+
+        res.add_header("Content-Encoding", "gzip");
+        for header in &mut res.headers {
+            if header.name.eq_ignore_ascii_case("ETag") && !header.value.starts_with("W/") {
+                header.value.insert_str(0, "W/");
+            }
+        }
+        Ok(())
+    }
+}
+
+impl HttpService for Gzip {
+    async fn request(&self, route: &str, req: &HttpRequest, body: &mut dyn HttpRead) -> HttpResult {
+        let mut res = self.service.request_raw(route, req, body).await?;
+        let eligible = match res.body {
+            HttpBody::Bytes(_) => true,
+            HttpBody::Stream(_) => self.streams,
+            _ => false,
+        };
+        if !eligible || !compressible(&res.content_type) {
+            return Ok(res);
+        }
+        // Caches must keep both variants apart, even if this one is not compressed
+        add_vary(&mut res);
+        if accepts_gzip(req) { self.compress(&mut res)?; }
+        Ok(res)
+    }
+
+    fn filter(&self, route: &str, req: &HttpRequest) -> HttpResult<()> {
+        self.service.filter_raw(route, req)
+    }
+}
+
+/// Compresses a stream as it is read
+struct GzipStream {
+    inner: Box<dyn AsyncRead + Send + Unpin>,
+    /// `None` after the end of the stream
+    encoder: Option<GzEncoder<Vec<u8>>>,
+    /// Compressed data which was not read yet
+    out: Vec<u8>,
+    pos: usize,
+}
+
+impl GzipStream {
+    fn new(inner: Box<dyn AsyncRead + Send + Unpin>) -> GzipStream {
+        let encoder = GzEncoder::new(vec![], Compression::default());
+        GzipStream { inner, encoder: Some(encoder), out: vec![], pos: 0 }
+    }
+}
+
+impl AsyncRead for GzipStream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            if this.pos < this.out.len() {
+                let n = buf.remaining().min(this.out.len() - this.pos);
+                buf.put_slice(&this.out[this.pos..this.pos + n]);
+                this.pos += n;
+                return Poll::Ready(Ok(()));
+            }
+            let Some(encoder) = &mut this.encoder else { return Poll::Ready(Ok(())) };
+
+            let mut chunk = [0; 8192];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk))?;
+            if chunk.filled().is_empty() {
+                this.out = this.encoder.take().unwrap().finish()?;
+            } else {
+                encoder.write_all(chunk.filled())?;
+                // Sync flush, so everything read so far can be decompressed by the client
+                encoder.flush()?;
+                this.out = mem::take(encoder.get_mut());
+            }
+            this.pos = 0;
+        }
+    }
+}
+
+/// Explicit `gzip` wins over `*`, so `gzip;q=0, *` is a refusal
+fn accepts_gzip(req: &HttpRequest) -> bool {
+    let accepted = qvalue::parse(req.get_header("Accept-Encoding").unwrap_or_default());
+    let find = |f: fn(&str) -> bool| accepted.iter().find(|(token, _)| f(token)).map(|&(_, q)| q > 0.0);
+    find(|token| token.eq_ignore_ascii_case("gzip") || token.eq_ignore_ascii_case("x-gzip"))
+        .or_else(|| find(|token| token == "*"))
+        .unwrap_or(false)
+}
+
+/// Images, video, audio, fonts and archives are already compressed
+fn compressible(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+    mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || ["application/json", "application/javascript", "application/xml", "application/wasm"].contains(&mime.as_str())
+}
+
+fn add_vary(res: &mut HttpResponse) {
+    let vary = res.headers.iter_mut().find(|h| h.name.eq_ignore_ascii_case("Vary"));
+    match vary {
+        Some(vary) => {
+            let listed = vary.value.split(',').map(str::trim).any(|name| name == "*" || name.eq_ignore_ascii_case("Accept-Encoding"));
+            if !listed { vary.value.push_str(", Accept-Encoding"); }
+        }
+        None => { res.add_header("Vary", "Accept-Encoding"); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+    use tokio::io::AsyncReadExt;
+
+    use super::{accepts_gzip, compressible, add_vary, Gzip};
+    use crate::core::{HttpService, HttpResult, HttpRead};
+    use crate::reqres::{res, header, HttpBody, HttpRequest};
+    use crate::reqres::sse::{HttpSse, HttpSseEvent};
+    use crate::service::DefaultService;
+
+    fn gunzip(data: &[u8]) -> String {
+        let mut out = String::new();
+        GzDecoder::new(data).read_to_string(&mut out).unwrap();
+        out
+    }
+
+    fn accept(value: &str) -> HttpRequest {
+        HttpRequest { headers: vec![header("Accept-Encoding", value)], ..Default::default() }
+    }
+
+    #[test]
+    fn negotiation() {
+        assert!(accepts_gzip(&accept("gzip, deflate, br")));
+        assert!(accepts_gzip(&accept("br;q=1, *;q=0.1")));
+        assert!(!accepts_gzip(&accept("gzip;q=0, *")));
+        assert!(!accepts_gzip(&accept("br")));
+        assert!(!accepts_gzip(&HttpRequest::default()));
+
+        assert!(compressible("text/html; charset=utf-8"));
+        assert!(compressible("image/svg+xml"));
+        assert!(!compressible("image/png"));
+    }
+
+    #[test]
+    fn compression() {
+        let gzip = Gzip::new(DefaultService);
+        let text = "hello world ".repeat(200);
+        let mut res = res::text(text.clone());
+        res.add_header("ETag", "\"abc\"").add_header("Vary", "Accept");
+        add_vary(&mut res);
+        gzip.compress(&mut res).unwrap();
+        assert_eq!(res.get_header("Content-Encoding"), Some("gzip"));
+        assert_eq!(res.get_header("ETag"), Some("W/\"abc\""));
+        assert_eq!(res.get_header("Vary"), Some("Accept, Accept-Encoding"));
+        let HttpBody::Bytes(body) = &res.body else { panic!("not bytes") };
+        assert_eq!(gunzip(body), text);
+
+        let mut small = res::text("hi");
+        gzip.compress(&mut small).unwrap();
+        assert!(!small.has_header("Content-Encoding"));
+    }
+
+    #[test]
+    fn threshold() {
+        let mut gzip = Gzip::new(DefaultService);
+        gzip.min_size(10);
+        let mut res = res::text("hello world ".repeat(2));
+        gzip.compress(&mut res).unwrap();
+        assert!(res.has_header("Content-Encoding"));
+
+        let mut short_stream = res::stream_with_len("text/plain", 5, &b"hello"[..]);
+        gzip.compress(&mut short_stream).unwrap();
+        assert!(!short_stream.has_header("Content-Encoding"));
+    }
+
+    #[test]
+    fn stream() {
+        let gzip = Gzip::new(DefaultService);
+        let text = "hello world ".repeat(2000);
+        let mut res = res::stream_with_len("text/plain", text.len() as u64, std::io::Cursor::new(text.clone().into_bytes()));
+        gzip.compress(&mut res).unwrap();
+        assert_eq!(res.get_header("Content-Encoding"), Some("gzip"));
+        assert!(!res.has_header("Content-Length"));
+
+        let HttpBody::Stream(mut stream) = res.body else { panic!("not a stream") };
+        let mut body = vec![];
+        crate::server::tokio_rt().unwrap().block_on(stream.read_to_end(&mut body)).unwrap();
+        assert!(body.len() < text.len());
+        assert_eq!(gunzip(&body), text);
+    }
+
+    struct Ticks;
+    impl HttpSse for Ticks {
+        async fn next(&mut self) -> Option<HttpSseEvent> {
+            Some(HttpSseEvent::new("tick"))
+        }
+    }
+
+    struct Events;
+    impl HttpService for Events {
+        async fn request(&self, _route: &str, _req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+            Ok(res::sse(Ticks))
+        }
+    }
+
+    #[test]
+    fn sse_passes_through() {
+        let gzip = Gzip::new(Events);
+        let res = crate::server::tokio_rt().unwrap().block_on(gzip.request("/", &accept("gzip"), &mut &b""[..])).unwrap();
+        assert!(matches!(res.body, HttpBody::Sse(_)));
+        assert!(!res.has_header("Content-Encoding"));
+    }
+}
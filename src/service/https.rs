@@ -0,0 +1,59 @@
+//! HTTP to HTTPS redirect service
+
+use crate::core::{HttpService, HttpResult, HttpRead};
+use crate::reqres::{res, HttpRequest, StatusCode};
+
+/// Redirects every request to the same URL on HTTPS, built from `Host` and the route
+///
+/// Run it on the plaintext port next to your HTTPS server:
+/// ```
+/// # use dhttp::prelude::*;
+/// use dhttp::service::HttpsRedirect;
+///
+/// let mut http = HttpServer::new();
+/// http.service(HttpsRedirect::new(true));
+/// // serve_tcp("0.0.0.0:80", http)
+/// ```
+///
+/// Method and body are kept by the redirect (`307`/`308`)
+pub struct HttpsRedirect {
+    permanent: bool,
+    port: Option<u16>,
+}
+
+impl HttpsRedirect {
+    /// Permanent redirects (`308`) can be cached by browsers, use temporary ones (`307`) while testing
+    pub fn new(permanent: bool) -> HttpsRedirect {
+        HttpsRedirect { permanent, port: None }
+    }
+
+    /// Redirects to this port instead of the default 443
+    pub fn port(&mut self, port: u16) -> &mut Self {
+        self.port = Some(port);
+        self
+    }
+}
+
+impl HttpService for HttpsRedirect {
+    async fn request(&self, _route: &str, req: &HttpRequest, _body: &mut dyn HttpRead) -> HttpResult {
+        let host = req.get_header("Host").filter(|host| !host.is_empty()).ok_or(StatusCode::BAD_REQUEST)?;
+        // Strip the plaintext port, keeping IPv6 brackets
+        let host = match host.rsplit_once(':') {
+            Some((name, port)) if !port.contains(']') => name,
+            _ => host,
+        };
+        let dest = match self.port {
+            Some(port) => format!("https://{host}:{port}{}", req.route),
+            None => format!("https://{host}{}", req.route),
+        };
+        if self.permanent {
+            Ok(res::permanent_redirect(dest))
+        } else {
+            Ok(res::temporary_redirect(dest))
+        }
+    }
+
+    fn filter(&self, _route: &str, _req: &HttpRequest) -> HttpResult<()> {
+        Ok(())
+    }
+}
@@ -1,3 +1,6 @@
+use std::io;
+use std::net::IpAddr;
+
 use chrono_lite::{Tm, time, localtime};
 use parseagent::Guess;
 
@@ -8,6 +11,13 @@ use crate::util::escape;
 /// Default logger implementation
 pub struct DefaultLogger;
 impl DefaultLogger {
+    fn date(&self) -> String {
+        let Tm { tm_mday, tm_mon, tm_year, tm_hour, tm_min, tm_sec, .. } = localtime(time()).expect("date out of range");
+        let year = tm_year + 1900;
+        let month = tm_mon + 1;
+        format!("{tm_hour:02}:{tm_min:02}:{tm_sec:02} {tm_mday:02}-{month:02}-{year}")
+    }
+
     fn format(&self, req: &HttpRequest, res: &HttpResponse) -> String {
         let addr = req.addr;
         let method = escape::control_sequences(req.method.as_str());
@@ -16,10 +26,7 @@ impl DefaultLogger {
         let code = res.code;
         let desc = code.as_str();
 
-        let Tm { tm_mday, tm_mon, tm_year, tm_hour, tm_min, tm_sec, .. } = localtime(time()).expect("date out of range");
-        let year = tm_year + 1900;
-        let month = tm_mon + 1;
-        let date = format_args!("{tm_hour:02}:{tm_min:02}:{tm_sec:02} {tm_mday:02}-{month:02}-{year}");
+        let date = self.date();
 
         let agent = req.get_header("User-Agent").unwrap_or_default();
         let agent = Guess::new(agent).to_string();
@@ -36,4 +43,20 @@ impl HttpLogger for DefaultLogger {
     fn err(&self, req: &HttpRequest, res: &HttpResponse, error: &dyn HttpError) {
         println!("{} ({}: {})", self.format(req, res), error.name(), error);
     }
+
+    fn aborted(&self, req: &HttpRequest, res: &HttpResponse, error: &io::Error) {
+        println!("{} (aborted: {})", self.format(req, res), error);
+    }
+
+    fn accept_err(&self, error: &io::Error) {
+        println!("[{}] DrakoHTTP critical error: connection not accepted: {error}", self.date());
+    }
+
+    fn conn_err(&self, addr: IpAddr, error: &io::Error) {
+        println!("[{}] {addr} connection closed: {error}", self.date());
+    }
+
+    fn shutdown(&self, connections: usize) {
+        println!("[{}] Shutting down, {connections} connections open", self.date());
+    }
 }
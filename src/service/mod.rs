@@ -3,14 +3,32 @@
 mod defaultservice;
 pub use defaultservice::DefaultService;
 mod router;
-pub use router::Router;
+pub use router::{Router, RouteKind, MethodNotAllowed};
+mod portrouter;
+pub use portrouter::PortRouter;
 mod files;
-pub use files::Files;
+pub use files::{Files, SingleFile};
 mod redirect;
 pub use redirect::Redirect;
+mod https;
+pub use https::HttpsRedirect;
+mod echo;
+pub use echo::Echo;
+mod cache;
+pub use cache::Cache;
+#[cfg(feature = "gzip")]
+mod gzip;
+#[cfg(feature = "gzip")]
+pub use gzip::Gzip;
+mod security;
+pub use security::{SecurityHeaders, FrameOptions, ReferrerPolicy, PermissionsPolicy};
+mod buffered;
+pub use buffered::Buffered;
+mod func;
+pub use func::{service_fn, ServiceFn, FnService};
 
 mod log;
 pub use log::DefaultLogger;
 
 mod errorpage;
-pub use errorpage::ErrorPageHandler;
+pub use errorpage::{ErrorPageHandler, ErrorPageColors};
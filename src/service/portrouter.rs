@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+
+use crate::core::{HttpServiceRaw, HttpService, HttpResult, HttpRead};
+use crate::reqres::{HttpRequest, StatusCode};
+
+/// Dispatches requests by the local port they came in on ([`HttpRequest::local_addr`])
+///
+/// Useful when the same server listens on several ports:
+/// ```
+/// # use dhttp::service::{PortRouter, DefaultService, Files};
+/// let mut router = PortRouter::new();
+/// router.add(8080, DefaultService);
+/// router.add(9090, Files::new("admin"));
+/// ```
+///
+/// # Errors
+/// When port is not matched and there is no fallback, [`PortRouter`] fires a `StatusCode(404)`
+#[derive(Default)]
+pub struct PortRouter {
+    ports: HashMap<u16, Box<dyn HttpServiceRaw>>,
+    fallback: Option<Box<dyn HttpServiceRaw>>,
+}
+
+impl PortRouter {
+    /// Creates an empty `PortRouter`
+    pub fn new() -> PortRouter {
+        PortRouter::default()
+    }
+
+    /// Serves this port with a service
+    pub fn add(&mut self, port: u16, service: impl HttpServiceRaw) -> &mut Self {
+        self.ports.insert(port, Box::new(service));
+        self
+    }
+
+    /// Serves the ports which were not added
+    pub fn fallback(&mut self, service: impl HttpServiceRaw) -> &mut Self {
+        self.fallback = Some(Box::new(service));
+        self
+    }
+
+    fn find(&self, req: &HttpRequest) -> Option<&dyn HttpServiceRaw> {
+        self.ports.get(&req.local_addr.port()).or(self.fallback.as_ref()).map(|service| &**service)
+    }
+}
+
+impl HttpService for PortRouter {
+    async fn request(&self, route: &str, req: &HttpRequest, body: &mut dyn HttpRead) -> HttpResult {
+        match self.find(req) {
+            Some(service) => service.request_raw(route, req, body).await,
+            None => Err(StatusCode::NOT_FOUND.into()),
+        }
+    }
+
+    fn filter(&self, route: &str, req: &HttpRequest) -> HttpResult<()> {
+        match self.find(req) {
+            Some(service) => service.filter_raw(route, req),
+            None => Err(StatusCode::NOT_FOUND.into()),
+        }
+    }
+}
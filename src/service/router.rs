@@ -1,7 +1,18 @@
 use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
 
-use crate::core::{HttpServiceRaw, HttpService, HttpResult, HttpRead};
-use crate::reqres::{HttpRequest, StatusCode};
+use crate::core::{HttpServiceRaw, HttpService, HttpError, HttpErrorType, HttpResult, HttpRead};
+use crate::reqres::{HttpRequest, HttpHeader, HttpMethod, StatusCode};
+
+/// Kind of a route in [`Router`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteKind {
+    /// Matches only this route
+    Exact,
+    /// Matches anything under this route
+    Nested,
+}
 
 /// Router is a service that nests other services on chosen routes
 ///
@@ -35,19 +46,76 @@ use crate::reqres::{HttpRequest, StatusCode};
 /// router.add("/files", Redirect::new("/files/"));
 /// ```
 ///
+/// Routes can also be registered for a single method, then other methods fail with
+/// `405 Method not allowed` (see [`MethodNotAllowed`]):
+/// ```
+/// # use dhttp::service::{Router, DefaultService, Echo};
+/// # let mut router = Router::new();
+/// router.get("/items", DefaultService).post("/items", Echo::new(1024));
+/// ```
+/// `HEAD` requests go to the `GET` service if there is no `HEAD` one. Service added with
+/// [`add`](Router::add) takes the methods that were not registered separately
+///
+/// The chosen service still runs its own [`filter`](HttpService::filter), which also checks the method.
+/// The default one only allows `GET` and `HEAD`, so a service for other methods has to override it, like [`Echo`](super::Echo) does
+///
 /// Also, nested routes strip their prefix - so `/files/something` becomes `/something` in the `route` argument. Original route is still accessible via `req.route`
 ///
-/// Nested routes are implemented with a linear search, consider something more optimized
-/// if you have thousands of them (like a HashMap)
+/// [`HttpServer`](crate::server::HttpServer) normalizes routes before routing, so `/a//b` and `/a/./b` match `/a/b`
+/// (see [`path::normalize`](crate::util::path::normalize))
+///
+/// Nested routes are looked up in a tree of path segments, so their number doesn't matter.
+/// If several of them match, the longest one wins
 ///
 /// # Errors
-/// When a route cannot be matched, [`Router`] fires a `StatusCode(404)`
+/// When a route cannot be matched, [`Router`] fires a `StatusCode(404)`.
+/// When it is matched, but not the method, it fires [`MethodNotAllowed`]
 #[derive(Default)]
 pub struct Router {
     /// Exact routes
-    exact: HashMap<String, Box<dyn HttpServiceRaw>>,
+    exact: HashMap<String, Route>,
     /// Nested routes
-    nested: Vec<(String, Box<dyn HttpServiceRaw>)>,
+    nested: Node,
+}
+
+/// Node of the nested routes tree, children are keyed by the next path segment
+#[derive(Default)]
+struct Node {
+    /// Nested route ending here, with its prefix
+    route: Option<(String, Route)>,
+    children: HashMap<String, Node>,
+}
+
+impl Node {
+    fn routes<'a>(&'a self, out: &mut Vec<&'a str>) {
+        if let Some((prefix, _)) = &self.route { out.push(prefix); }
+        for child in self.children.values() { child.routes(out); }
+    }
+}
+
+/// Services of one route
+#[derive(Default)]
+struct Route {
+    /// Added with [`Router::add`], takes any method
+    any: Option<Box<dyn HttpServiceRaw>>,
+    methods: Vec<(HttpMethod, Box<dyn HttpServiceRaw>)>,
+}
+
+impl Route {
+    fn find(&self, method: &HttpMethod) -> Result<&dyn HttpServiceRaw, MethodNotAllowed> {
+        let get = |method: &HttpMethod| self.methods.iter().find(|(m, _)| m == method).map(|(_, service)| &**service);
+        let found = get(method)
+            // HEAD is GET without a body
+            .or_else(|| if *method == HttpMethod::Head { get(&HttpMethod::Get) } else { None })
+            .or(self.any.as_deref());
+        found.ok_or_else(|| {
+            let mut allow: Vec<_> = self.methods.iter().map(|(m, _)| m.clone()).collect();
+            if allow.contains(&HttpMethod::Get) && !allow.contains(&HttpMethod::Head) {
+                allow.push(HttpMethod::Head);
+            }
+            MethodNotAllowed { allow }
+        })
+    }
 }
 
 impl Router {
@@ -56,55 +124,168 @@ impl Router {
         Router::default()
     }
 
-    /// Adds a new route
+    /// Adds a new route for any method
     pub fn add(&mut self, route: &str, service: impl HttpServiceRaw) -> &mut Self {
-        let mut route = route.to_string();
-        if route.ends_with("/*") { // */
-            route.pop(); route.pop();
-            self.nested.push((route, Box::new(service)));
-        } else {
-            self.exact.insert(route, Box::new(service));
-        }
+        self.entry(route).any = Some(Box::new(service));
+        self
+    }
+
+    /// Adds a new route for one method, replacing the previous service of this method
+    pub fn add_method(&mut self, method: HttpMethod, route: &str, service: impl HttpServiceRaw) -> &mut Self {
+        let entry = self.entry(route);
+        entry.methods.retain(|(m, _)| *m != method);
+        entry.methods.push((method, Box::new(service)));
         self
     }
 
-    fn find<'a, 'b>(&'a self, route: &'b str) -> Option<(&'b str, &'a dyn HttpServiceRaw)> {
+    /// Adds a new `GET` route, it also takes `HEAD` requests
+    pub fn get(&mut self, route: &str, service: impl HttpServiceRaw) -> &mut Self {
+        self.add_method(HttpMethod::Get, route, service)
+    }
+
+    /// Adds a new `POST` route
+    pub fn post(&mut self, route: &str, service: impl HttpServiceRaw) -> &mut Self {
+        self.add_method(HttpMethod::Post, route, service)
+    }
+
+    /// Adds a new `PUT` route
+    pub fn put(&mut self, route: &str, service: impl HttpServiceRaw) -> &mut Self {
+        self.add_method(HttpMethod::Put, route, service)
+    }
+
+    /// Adds a new `PATCH` route
+    pub fn patch(&mut self, route: &str, service: impl HttpServiceRaw) -> &mut Self {
+        self.add_method(HttpMethod::Patch, route, service)
+    }
+
+    /// Adds a new `DELETE` route
+    pub fn delete(&mut self, route: &str, service: impl HttpServiceRaw) -> &mut Self {
+        self.add_method(HttpMethod::Delete, route, service)
+    }
+
+    /// Services of this route, created if it is new
+    fn entry(&mut self, route: &str) -> &mut Route {
+        match route.strip_suffix("/*") { // */
+            Some(prefix) => {
+                let mut node = &mut self.nested;
+                // `/*` is the root
+                if !prefix.is_empty() {
+                    for segment in prefix.strip_prefix('/').unwrap_or(prefix).split('/') {
+                        node = node.children.entry(segment.to_string()).or_default();
+                    }
+                }
+                &mut node.route.get_or_insert_with(|| (prefix.to_string(), Route::default())).1
+            }
+            None => self.exact.entry(route.to_string()).or_default(),
+        }
+    }
+
+    /// Lists the registered routes
+    ///
+    /// Nested routes are listed without their `/*` suffix
+    pub fn routes(&self) -> impl Iterator<Item = (&str, RouteKind)> {
+        let exact = self.exact.keys().map(|route| (route.as_str(), RouteKind::Exact));
+        let mut nested = vec![];
+        self.nested.routes(&mut nested);
+        exact.chain(nested.into_iter().map(|route| (route, RouteKind::Nested)))
+    }
+
+    fn find<'a, 'b>(&'a self, route: &'b str, method: &HttpMethod) -> HttpResult<(&'b str, &'a dyn HttpServiceRaw)> {
         // remove url params part
         let mut route_withoutparams = route;
         if let Some(params_index) = route.find('?') {
             route_withoutparams = &route[..params_index];
         }
-        if let Some(service) = self.exact.get(route_withoutparams) {
-            return Some((route, &**service));
+        if let Some(entry) = self.exact.get(route_withoutparams) {
+            return Ok((route, entry.find(method)?));
         }
 
-        for (r, service) in &self.nested {
-            // compare prefix...
-            if let Some(route) = route.strip_prefix(r) {
-                // if leftover starts with /, then it matched a subsegment...
-                if route.starts_with("/") {
-                    return Some((route, &**service));
-                }
-                // otherwise, it didn't match anything (think of /files vs /files123)
+        // Walk down the tree one segment at a time, remembering the deepest route
+        let path = route_withoutparams;
+        let mut node = &self.nested;
+        let mut offset = 0;
+        let mut found = None;
+        // prefix matches only if the leftover starts with / (think of /files vs /files123)
+        while path[offset..].starts_with('/') {
+            if let Some((_, entry)) = &node.route {
+                found = Some((offset, entry));
             }
+            let rest = &path[offset + 1..];
+            let segment = &rest[..rest.find('/').unwrap_or(rest.len())];
+            let Some(child) = node.children.get(segment) else { break };
+            node = child;
+            offset += 1 + segment.len();
         }
 
-        None
+        match found {
+            // nested routes strip their prefix
+            Some((offset, entry)) => Ok((&route[offset..], entry.find(method)?)),
+            None => Err(StatusCode::NOT_FOUND.into()),
+        }
     }
 }
 
 impl HttpService for Router {
     async fn request(&self, route: &str, req: &HttpRequest, body: &mut dyn HttpRead) -> HttpResult {
-        match self.find(route) {
-            Some((route, service)) => service.request_raw(route, req, body).await,
-            None => Err(StatusCode::NOT_FOUND.into()),
-        }
+        let (route, service) = self.find(route, &req.method)?;
+        service.request_raw(route, req, body).await
     }
 
     fn filter(&self, route: &str, req: &HttpRequest) -> HttpResult<()> {
-        match self.find(route) {
-            Some((route, service)) => service.filter_raw(route, req),
-            None => Err(StatusCode::NOT_FOUND.into()),
-        }
+        let (route, service) = self.find(route, &req.method)?;
+        service.filter_raw(route, req)
+    }
+}
+
+/// Route exists, but not for this method. Fires a `405` with the `Allow` header
+#[derive(Debug, Clone)]
+pub struct MethodNotAllowed {
+    /// Methods this route has
+    pub allow: Vec<HttpMethod>,
+}
+
+impl fmt::Display for MethodNotAllowed {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("Method not allowed")
+    }
+}
+
+impl Error for MethodNotAllowed {}
+
+impl HttpError for MethodNotAllowed {
+    fn error_type(&self) -> HttpErrorType { HttpErrorType::Status }
+    fn status_code(&self) -> StatusCode { StatusCode::METHOD_NOT_ALLOWED }
+
+    fn headers(&self) -> Vec<HttpHeader> {
+        let allow: Vec<_> = self.allow.iter().map(HttpMethod::as_str).collect();
+        vec![HttpHeader { name: "Allow".to_string(), value: allow.join(", ") }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Router;
+    use crate::reqres::HttpMethod;
+    use crate::service::DefaultService;
+
+    fn leftover<'a>(router: &Router, route: &'a str) -> Option<&'a str> {
+        router.find(route, &HttpMethod::Get).ok().map(|(route, _)| route)
+    }
+
+    #[test]
+    fn nested_routes() {
+        let mut router = Router::new();
+        router.add("/files/*", DefaultService).add("/files/deep/*", DefaultService).add("/files/deep", DefaultService);
+        assert_eq!(leftover(&router, "/files/a/b"), Some("/a/b"));
+        assert_eq!(leftover(&router, "/files/deep/x?q=/y"), Some("/x?q=/y"));
+        assert_eq!(leftover(&router, "/files/deep"), Some("/files/deep"));
+        assert_eq!(leftover(&router, "/files/deeper"), Some("/deeper"));
+        assert_eq!(leftover(&router, "/files/?q"), Some("/?q"));
+        assert_eq!(leftover(&router, "/files"), None);
+        assert_eq!(leftover(&router, "/files123/a"), None);
+
+        router.add("/*", DefaultService);
+        assert_eq!(leftover(&router, "/files123/a"), Some("/files123/a"));
+        assert_eq!(router.routes().count(), 4);
     }
 }
@@ -0,0 +1,212 @@
+//! Security headers service
+
+use std::time::Duration;
+
+use crate::core::{HttpServiceRaw, HttpService, HttpResult, HttpRead};
+use crate::reqres::{HttpRequest, HttpResponse};
+
+// Half a year, what browsers require for the preload list is a year
+const DEFAULT_HSTS_MAX_AGE: Duration = Duration::from_secs(180 * 24 * 60 * 60);
+
+/// Adds hardening headers to responses of the inner service
+///
+/// Each header can be turned off, and headers already set by the service are kept. Defaults:
+/// - `Strict-Transport-Security: max-age=15552000` (ignored by browsers over plain HTTP)
+/// - `X-Content-Type-Options: nosniff`
+/// - `X-Frame-Options: SAMEORIGIN`
+/// - `Referrer-Policy: strict-origin-when-cross-origin`
+/// - no `Permissions-Policy`
+///
+/// Error responses are made later by the error handler, use [`HttpServer::default_headers`] if they need these too
+///
+/// ```
+/// # use dhttp::service::{DefaultService, SecurityHeaders, ReferrerPolicy, PermissionsPolicy};
+/// let mut service = SecurityHeaders::new(DefaultService);
+/// service
+///     .referrer_policy(Some(ReferrerPolicy::NoReferrer))
+///     .permissions_policy(Some(PermissionsPolicy::new().deny("camera").allow_self("fullscreen")));
+/// ```
+///
+/// [`HttpServer::default_headers`]: crate::server::HttpServer::default_headers
+pub struct SecurityHeaders {
+    service: Box<dyn HttpServiceRaw>,
+    hsts: Option<Duration>,
+    nosniff: bool,
+    frame_options: Option<FrameOptions>,
+    referrer_policy: Option<ReferrerPolicy>,
+    permissions_policy: Option<PermissionsPolicy>,
+}
+
+impl SecurityHeaders {
+    pub fn new(service: impl HttpServiceRaw) -> SecurityHeaders {
+        SecurityHeaders {
+            service: Box::new(service),
+            hsts: Some(DEFAULT_HSTS_MAX_AGE),
+            nosniff: true,
+            frame_options: Some(FrameOptions::SameOrigin),
+            referrer_policy: Some(ReferrerPolicy::StrictOriginWhenCrossOrigin),
+            permissions_policy: None,
+        }
+    }
+
+    /// `max-age` of `Strict-Transport-Security`
+    pub fn hsts(&mut self, max_age: Option<Duration>) -> &mut Self {
+        self.hsts = max_age;
+        self
+    }
+
+    pub fn nosniff(&mut self, nosniff: bool) -> &mut Self {
+        self.nosniff = nosniff;
+        self
+    }
+
+    pub fn frame_options(&mut self, frame_options: Option<FrameOptions>) -> &mut Self {
+        self.frame_options = frame_options;
+        self
+    }
+
+    pub fn referrer_policy(&mut self, referrer_policy: Option<ReferrerPolicy>) -> &mut Self {
+        self.referrer_policy = referrer_policy;
+        self
+    }
+
+    pub fn permissions_policy(&mut self, permissions_policy: Option<PermissionsPolicy>) -> &mut Self {
+        self.permissions_policy = permissions_policy;
+        self
+    }
+
+    fn apply(&self, res: &mut HttpResponse) {
+        let mut add = |name: &str, value: String| {
+            if !res.has_header(name) { res.add_header(name, value); }
+        };
+        if let Some(max_age) = self.hsts {
+            add("Strict-Transport-Security", format!("max-age={}", max_age.as_secs()));
+        }
+        if self.nosniff {
+            add("X-Content-Type-Options", "nosniff".to_string());
+        }
+        if let Some(frame_options) = self.frame_options {
+            add("X-Frame-Options", frame_options.as_str().to_string());
+        }
+        if let Some(referrer_policy) = self.referrer_policy {
+            add("Referrer-Policy", referrer_policy.as_str().to_string());
+        }
+        if let Some(permissions_policy) = &self.permissions_policy && !permissions_policy.0.is_empty() {
+            add("Permissions-Policy", permissions_policy.0.join(", "));
+        }
+    }
+}
+
+impl HttpService for SecurityHeaders {
+    async fn request(&self, route: &str, req: &HttpRequest, body: &mut dyn HttpRead) -> HttpResult {
+        let mut res = self.service.request_raw(route, req, body).await?;
+        self.apply(&mut res);
+        Ok(res)
+    }
+
+    fn filter(&self, route: &str, req: &HttpRequest) -> HttpResult<()> {
+        self.service.filter_raw(route, req)
+    }
+}
+
+/// Value of `X-Frame-Options`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameOptions {
+    /// Page can't be put in a frame
+    Deny,
+    /// Only pages of the same origin can put it in a frame
+    SameOrigin,
+}
+
+impl FrameOptions {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FrameOptions::Deny => "DENY",
+            FrameOptions::SameOrigin => "SAMEORIGIN",
+        }
+    }
+}
+
+/// Value of `Referrer-Policy`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ReferrerPolicy {
+    NoReferrer,
+    NoReferrerWhenDowngrade,
+    Origin,
+    OriginWhenCrossOrigin,
+    SameOrigin,
+    StrictOrigin,
+    StrictOriginWhenCrossOrigin,
+    UnsafeUrl,
+}
+
+impl ReferrerPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReferrerPolicy::NoReferrer => "no-referrer",
+            ReferrerPolicy::NoReferrerWhenDowngrade => "no-referrer-when-downgrade",
+            ReferrerPolicy::Origin => "origin",
+            ReferrerPolicy::OriginWhenCrossOrigin => "origin-when-cross-origin",
+            ReferrerPolicy::SameOrigin => "same-origin",
+            ReferrerPolicy::StrictOrigin => "strict-origin",
+            ReferrerPolicy::StrictOriginWhenCrossOrigin => "strict-origin-when-cross-origin",
+            ReferrerPolicy::UnsafeUrl => "unsafe-url",
+        }
+    }
+}
+
+/// Builder of `Permissions-Policy`, which limits browser features (`camera`, `geolocation`, ...)
+#[derive(Debug, Clone, Default)]
+pub struct PermissionsPolicy(Vec<String>);
+
+impl PermissionsPolicy {
+    pub fn new() -> PermissionsPolicy {
+        PermissionsPolicy::default()
+    }
+
+    /// Feature is disabled everywhere: `camera=()`
+    pub fn deny(self, feature: &str) -> Self {
+        self.allow(feature, &[])
+    }
+
+    /// Feature is allowed only for this origin: `fullscreen=(self)`
+    pub fn allow_self(self, feature: &str) -> Self {
+        self.allow(feature, &["self"])
+    }
+
+    /// Feature is allowed for these origins, `self` and `*` are written as is:
+    /// `geolocation=(self "https://maps.example.com")`
+    pub fn allow(mut self, feature: &str, origins: &[&str]) -> Self {
+        let origins: Vec<String> = origins.iter()
+            .map(|&origin| if origin == "self" || origin == "*" { origin.to_string() } else { format!("\"{origin}\"") })
+            .collect();
+        self.0.push(format!("{feature}=({})", origins.join(" ")));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reqres::res;
+
+    #[test]
+    fn headers() {
+        let policy = PermissionsPolicy::new()
+            .deny("camera")
+            .allow_self("fullscreen")
+            .allow("geolocation", &["self", "https://maps.example.com"]);
+        let mut service = SecurityHeaders::new(crate::service::DefaultService);
+        service.hsts(None).permissions_policy(Some(policy));
+
+        let mut res = res::text("hi");
+        res.add_header("X-Frame-Options", "DENY");
+        service.apply(&mut res);
+        assert_eq!(res.get_header("Strict-Transport-Security"), None);
+        assert_eq!(res.get_header("X-Content-Type-Options"), Some("nosniff"));
+        assert_eq!(res.get_all_headers("X-Frame-Options").collect::<Vec<_>>(), ["DENY"]);
+        assert_eq!(res.get_header("Referrer-Policy"), Some("strict-origin-when-cross-origin"));
+        assert_eq!(res.get_header("Permissions-Policy"), Some("camera=(), fullscreen=(self), geolocation=(self \"https://maps.example.com\")"));
+    }
+}
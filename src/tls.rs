@@ -0,0 +1,49 @@
+//! HTTPS with rustls
+
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::BufReader;
+use tokio_rustls::TlsAcceptor;
+
+use crate::server::{self, HttpServer, ServeOutcome};
+
+pub use tokio_rustls::rustls::ServerConfig;
+
+/// Clients that don't finish the handshake in time are disconnected
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Starts handling HTTPS connections on a given [`HttpServer`]
+///
+/// Works like [`serve_tcp`](crate::serve_tcp), but every connection starts with a TLS handshake.
+/// Requests from these connections are [`secure`](crate::reqres::HttpRequest::secure).
+/// A failed handshake only closes its own connection, it is reported to [`HttpLogger::conn_err`]
+///
+/// Set `alpn_protocols` of the config to `http/1.1`, HTTP/2 is not supported.
+/// For mutual TLS, build the config `with_client_cert_verifier`, then the client's certificates
+/// are in [`peer_certificates`](crate::reqres::HttpRequest::peer_certificates)
+///
+/// [`HttpLogger::conn_err`]: crate::core::HttpLogger::conn_err
+pub async fn serve_tls(addr: &str, server: impl Into<Arc<HttpServer>>, config: impl Into<Arc<ServerConfig>>) -> io::Result<ServeOutcome> {
+    serve_tls_with_shutdown(addr, server, config, server::ctrl_c()).await
+}
+
+/// Same as [`serve_tls`], but stops when `shutdown` completes, see [`serve_tcp_with_shutdown`](crate::serve_tcp_with_shutdown)
+pub async fn serve_tls_with_shutdown(addr: &str, server: impl Into<Arc<HttpServer>>, config: impl Into<Arc<ServerConfig>>, shutdown: impl Future<Output = ()>) -> io::Result<ServeOutcome> {
+    let acceptor = TlsAcceptor::from(config.into());
+    server::serve_listener(server::bind(addr)?, server.into(), shutdown, move |server, conn, mut info| {
+        let acceptor = acceptor.clone();
+        async move {
+            let conn = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(conn)).await {
+                Ok(conn) => conn?,
+                Err(_) => return Err(io::ErrorKind::TimedOut.into()),
+            };
+            info.secure = true;
+            // Only sent with client authentication, see `WebPkiClientVerifier`
+            let certificates = conn.get_ref().1.peer_certificates();
+            info.peer_certificates = certificates.map(|chain| Arc::new(chain.iter().map(|cert| cert.to_vec()).collect()));
+            server.handle_connection(BufReader::new(conn), info).await
+        }
+    }).await
+}
@@ -31,3 +31,34 @@ pub(crate) fn control_sequences(s: &str) -> String {
     }
     out
 }
+
+/// Escapes text to be put into HTML
+pub(crate) fn html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+/// Percent-encodes characters which can't appear in a URI, like spaces and non-ASCII
+///
+/// Reserved characters and existing `%XX` escapes are kept, so the URI keeps its meaning
+pub(crate) fn uri(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for &byte in s.as_bytes() {
+        if byte.is_ascii_graphic() && !b"\"<>\\^`{|}".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            write!(&mut out, "%{byte:02X}").unwrap();
+        }
+    }
+    out
+}
@@ -1,7 +1,9 @@
 // Loosely based on futures_lite
 // I didn't want to enable macros in tokio nor to enable futures_lite
 
-use std::pin::Pin;
+use std::collections::VecDeque;
+use std::pin::{Pin, pin};
+use std::future::poll_fn;
 use std::task::{Context, Poll};
 
 use pin_project_lite::pin_project;
@@ -43,3 +45,43 @@ where
         Poll::Pending
     }
 }
+
+/// Polls all futures concurrently, outputs are taken in the original order as soon as they and all earlier ones are ready
+pub(crate) struct Ordered<F: Future + Unpin> {
+    futures: VecDeque<(Option<F>, Option<F::Output>)>,
+}
+
+impl<F: Future + Unpin> Ordered<F> {
+    pub(crate) fn new(futures: Vec<F>) -> Ordered<F> {
+        Ordered { futures: futures.into_iter().map(|f| (Some(f), None)).collect() }
+    }
+
+    /// Next output, `None` when all were taken
+    pub(crate) async fn next(&mut self) -> Option<F::Output> {
+        poll_fn(|cx| {
+            for (f, out) in &mut self.futures {
+                if let Some(fut) = f.as_mut() && let Poll::Ready(t) = Pin::new(fut).poll(cx) {
+                    *out = Some(t);
+                    *f = None;
+                }
+            }
+            match self.futures.front_mut().map(|(_, out)| out.take()) {
+                None => Poll::Ready(None),
+                Some(None) => Poll::Pending,
+                Some(Some(t)) => {
+                    self.futures.pop_front();
+                    Poll::Ready(Some(t))
+                }
+            }
+        }).await
+    }
+}
+
+/// Polls the future once, returns `None` if it is not ready yet
+pub(crate) async fn now_or_never<F: Future>(f: F) -> Option<F::Output> {
+    let mut f = pin!(f);
+    poll_fn(|cx| match f.as_mut().poll(cx) {
+        Poll::Ready(t) => Poll::Ready(Some(t)),
+        Poll::Pending => Poll::Ready(None),
+    }).await
+}
@@ -0,0 +1,157 @@
+//! Filtering of client IP addresses
+//! # Example
+//! ```
+//! # use dhttp::util::ipfilter::IpFilter;
+//! let mut filter = IpFilter::new();
+//! filter.allow("10.0.0.0/8".parse().unwrap());
+//! filter.deny("10.0.0.1/32".parse().unwrap());
+//! assert!(filter.allows("10.1.2.3".parse().unwrap()));
+//! assert!(!filter.allows("10.0.0.1".parse().unwrap()));
+//! assert!(!filter.allows("192.168.0.1".parse().unwrap()));
+//! ```
+
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::error::Error;
+use std::fmt;
+
+/// IP address range, like `192.168.0.0/16` or `fd00::/8`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// Constructs a new range, returns `None` if prefix is longer than the address
+    pub fn new(addr: IpAddr, prefix: u8) -> Option<Cidr> {
+        let addr = addr.to_canonical();
+        if prefix > bits(addr) { return None; }
+        Some(Cidr { addr, prefix })
+    }
+
+    /// Checks if this range contains an address
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => prefix_eq(u32::from(net).into(), u32::from(ip).into(), 32 - self.prefix),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => prefix_eq(net.into(), ip.into(), 128 - self.prefix),
+            _ => false,
+        }
+    }
+}
+
+fn bits(addr: IpAddr) -> u8 {
+    if addr.is_ipv4() { 32 } else { 128 }
+}
+
+// Compares everything except `host_bits` lowest bits
+fn prefix_eq(a: u128, b: u128, host_bits: u8) -> bool {
+    (a ^ b).checked_shr(host_bits.into()).unwrap_or(0) == 0
+}
+
+impl FromStr for Cidr {
+    type Err = InvalidCidr;
+
+    /// Parses a range, a plain address is parsed as a range of one address
+    fn from_str(s: &str) -> Result<Cidr, InvalidCidr> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| InvalidCidr)?;
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().map_err(|_| InvalidCidr)?,
+            None => bits(addr.to_canonical()),
+        };
+        Cidr::new(addr, prefix).ok_or(InvalidCidr)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Error returned when parsing a [`Cidr`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidCidr;
+
+impl fmt::Display for InvalidCidr {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.write_str("invalid CIDR address range")
+    }
+}
+
+impl Error for InvalidCidr {}
+
+/// Allow and deny lists of IP ranges
+///
+/// Denied ranges take precedence. Empty allow list allows everything
+#[derive(Debug, Clone, Default)]
+pub struct IpFilter {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+}
+
+impl IpFilter {
+    /// Creates a filter that allows everything
+    pub fn new() -> IpFilter {
+        IpFilter::default()
+    }
+
+    /// Adds a range to the allow list
+    pub fn allow(&mut self, range: Cidr) -> &mut Self {
+        self.allow.push(range);
+        self
+    }
+
+    /// Adds a range to the deny list
+    pub fn deny(&mut self, range: Cidr) -> &mut Self {
+        self.deny.push(range);
+        self
+    }
+
+    /// Checks if this address is allowed
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|range| range.contains(ip)) { return false; }
+        self.allow.is_empty() || self.allow.iter().any(|range| range.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cidr, InvalidCidr};
+
+    fn contains(range: &str, ip: &str) -> bool {
+        range.parse::<Cidr>().unwrap().contains(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn v4() {
+        assert!(contains("192.168.0.0/16", "192.168.255.1"));
+        assert!(!contains("192.168.0.0/16", "192.169.0.1"));
+        assert!(contains("0.0.0.0/0", "8.8.8.8"));
+        assert!(contains("8.8.8.8", "8.8.8.8"));
+        assert!(!contains("8.8.8.8", "8.8.8.9"));
+        assert!(contains("127.0.0.0/8", "::ffff:127.0.0.1"));
+        assert!(!contains("0.0.0.0/0", "::1"));
+    }
+
+    #[test]
+    fn v6() {
+        assert!(contains("fd00::/8", "fd12:3456::1"));
+        assert!(!contains("fd00::/8", "fe80::1"));
+        assert!(contains("::/0", "2001:db8::1"));
+        assert!(contains("::1", "::1"));
+        assert!(!contains("::/0", "127.0.0.1"));
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!("10.0.0.0/33".parse::<Cidr>(), Err(InvalidCidr));
+        assert_eq!("::/129".parse::<Cidr>(), Err(InvalidCidr));
+        assert_eq!("10.0.0.0/".parse::<Cidr>(), Err(InvalidCidr));
+        assert_eq!("dragon/8".parse::<Cidr>(), Err(InvalidCidr));
+    }
+}
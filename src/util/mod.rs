@@ -2,8 +2,14 @@
 
 pub mod httpdate;
 pub mod path;
+pub mod ipfilter;
+pub mod qvalue;
 pub(crate) mod escape;
 pub(crate) mod future;
 
 mod hex;
 pub(crate) use hex::hex;
+mod base64;
+pub(crate) use base64::base64;
+mod sha1;
+pub(crate) use sha1::sha1;
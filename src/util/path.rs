@@ -1,5 +1,6 @@
 ﻿//! Path utilities
 
+use std::borrow::Cow;
 use std::path::{Path, PathBuf};
 use std::error::Error;
 use std::fmt;
@@ -32,6 +33,32 @@ pub fn sanitize(route: &str) -> Result<PathBuf, DangerousPathError> {
     not_implemented
 }
 
+/// Normalizes the path part of a route: collapses `//` and removes `.` segments, query is kept as is
+///
+/// `..` segments are rejected instead of being resolved, browsers resolve them before sending anyway.
+/// Routes which don't start with `/` (like `*`) are returned unchanged
+pub fn normalize(route: &str) -> Result<Cow<'_, str>, DangerousPathError> {
+    let (path, query) = route.split_at(route.find('?').unwrap_or(route.len()));
+    if !path.starts_with('/') { return Ok(Cow::Borrowed(route)); }
+
+    let segments = path[1..].split('/');
+    if segments.clone().any(|s| s == "..") { return Err(DangerousPathError::DangerousPath); }
+    if !path.contains("//") && !segments.clone().any(|s| s == ".") { return Ok(Cow::Borrowed(route)); }
+
+    let mut out = String::with_capacity(route.len());
+    let mut trailing = false;
+    for segment in segments {
+        // `/a/.` is the same as `/a/`
+        trailing = segment.is_empty() || segment == ".";
+        if trailing { continue; }
+        out.push('/');
+        out.push_str(segment);
+    }
+    if trailing || out.is_empty() { out.push('/'); }
+    out.push_str(query);
+    Ok(Cow::Owned(out))
+}
+
 // TODO: preceding / can be removed without allocation
 // Another TODO: paths without preceding / should return bad request
 
@@ -113,7 +140,7 @@ pub fn encode(path: &Path) -> String {
 // TODO: test C:file on actix and " .." on dhttp
 #[cfg(test)]
 mod tests {
-    use super::{sanitize_win, sanitize_unix, DangerousPathError};
+    use super::{sanitize_win, sanitize_unix, normalize, DangerousPathError};
     use DangerousPathError::*;
     #[test]
     fn win() {
@@ -145,4 +172,19 @@ mod tests {
         assert!(sanitize_unix(b"\\..\\This is a filename").is_ok());
         assert!(sanitize_unix(b"/C:/Windows").is_ok());
     }
+    #[test]
+    fn normalize_route() {
+        assert_eq!(normalize("/a/b").unwrap(), "/a/b");
+        assert_eq!(normalize("/a//b").unwrap(), "/a/b");
+        assert_eq!(normalize("//a/b/").unwrap(), "/a/b/");
+        assert_eq!(normalize("/a/./b").unwrap(), "/a/b");
+        assert_eq!(normalize("/a/.").unwrap(), "/a/");
+        assert_eq!(normalize("/./").unwrap(), "/");
+        assert_eq!(normalize("//").unwrap(), "/");
+        assert_eq!(normalize("/a//b?x=//./").unwrap(), "/a/b?x=//./");
+        assert_eq!(normalize("/a/.b/..c").unwrap(), "/a/.b/..c");
+        assert_eq!(normalize("*").unwrap(), "*");
+        assert_eq!(normalize("/a/../b"), Err(DangerousPath));
+        assert_eq!(normalize("/.."), Err(DangerousPath));
+    }
 }
@@ -0,0 +1,73 @@
+//! Quality values of `Accept`, `Accept-Encoding`, `Accept-Language` and `TE` headers
+
+/// Parses a list like `gzip;q=0.8, br, *;q=0` into `(token, q)` pairs, highest `q` first
+///
+/// Entries without `q` get `1.0`, ties keep the order of the header. Entries with `q=0`
+/// are kept, because they exclude the token from wildcards. Other parameters (`text/html;level=1`)
+/// are dropped, and malformed entries (empty token or invalid `q`) are skipped
+/// ```
+/// # use dhttp::util::qvalue;
+/// assert_eq!(qvalue::parse("gzip;q=0.8, br, *;q=0"), vec![("br", 1.0), ("gzip", 0.8), ("*", 0.0)]);
+/// ```
+pub fn parse(header: &str) -> Vec<(&str, f32)> {
+    let mut out: Vec<_> = header.split(',').filter_map(parse_item).collect();
+    // Stable, so ties keep their order
+    out.sort_by(|a, b| b.1.total_cmp(&a.1));
+    out
+}
+
+/// Parses `token;q=0.5` into `(token, 0.5)`
+fn parse_item(item: &str) -> Option<(&str, f32)> {
+    let mut params = item.split(';');
+    let token = params.next()?.trim();
+    if token.is_empty() { return None; }
+    let mut q = 1.0;
+    for param in params {
+        if let Some((name, value)) = param.split_once('=') && name.trim().eq_ignore_ascii_case("q") {
+            q = weight(value.trim())?;
+        }
+    }
+    Some((token, q))
+}
+
+/// Only `0`-`1` with up to 3 decimals are valid, `parse()` would also take `1e0`, `+1` or `NaN`
+fn weight(s: &str) -> Option<f32> {
+    let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+    if !matches!(int, "0" | "1") || frac.len() > 3 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let q: f32 = s.trim_end_matches('.').parse().ok()?;
+    (q <= 1.0).then_some(q)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn order() {
+        assert_eq!(parse(""), vec![]);
+        assert_eq!(parse("a, b, c"), vec![("a", 1.0), ("b", 1.0), ("c", 1.0)]);
+        assert_eq!(parse("a;q=0.1, b;q=0.9, c"), vec![("c", 1.0), ("b", 0.9), ("a", 0.1)]);
+        assert_eq!(parse("a;q=0.5,b;q=0.5"), vec![("a", 0.5), ("b", 0.5)]);
+        assert_eq!(parse("*, en;q=0"), vec![("*", 1.0), ("en", 0.0)]);
+    }
+
+    #[test]
+    fn params() {
+        assert_eq!(parse("text/html;level=1;q=0.7"), vec![("text/html", 0.7)]);
+        assert_eq!(parse(" a ; Q = 0.5 "), vec![("a", 0.5)]);
+        assert_eq!(parse("a;charset=utf-8"), vec![("a", 1.0)]);
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(parse(",, ;q=1, a"), vec![("a", 1.0)]);
+        assert_eq!(parse("a;q=, b"), vec![("b", 1.0)]);
+        assert_eq!(parse("a;q=2, b;q=1.5, c;q=-0"), vec![]);
+        assert_eq!(parse("a;q=1.001, b;q=0.1234"), vec![]);
+        assert_eq!(parse("a;q=NaN, b;q=inf, c;q=1e0, d;q=+1"), vec![]);
+        assert_eq!(parse("a;q=.5, b;q=0.5x"), vec![]);
+        assert_eq!(parse("a;q=1., b;q=1.000, c;q=0."), vec![("a", 1.0), ("b", 1.0), ("c", 0.0)]);
+    }
+}